@@ -1,6 +1,36 @@
-use clap::Subcommand;
+use std::env;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+
+use chrono::{Duration, Local, NaiveDate};
+use clap::builder::BoolishValueParser;
+use clap::{ArgAction, Subcommand};
+use log::{debug, info};
+#[cfg(feature = "json-store")]
+use clap::ValueEnum;
 use rusqlite::Connection;
-use crate::db::task::{delete_task, insert_task, read_tasks, update_task_status};
+use serde::Serialize;
+use crate::completions::{write_completions, write_task_ids, CompletionShell};
+use crate::db::reminder::{clear_task_reminder, local_remind_at, parse_remind_at, set_task_reminder};
+use crate::db::backup::{backup_db, restore_db, rotate_backups};
+use crate::db::stats::{compute_stats, format_seconds};
+use crate::db::sync::{open_other_db, sync_databases};
+use crate::db::time_entry::{format_duration, start_timer, stop_timer, task_duration, time_report};
+use crate::db::selection::{parse_filter, resolve_task_ref};
+use crate::db::store::{StoreError, StoreResult, TaskStore};
+use crate::db::task::Task;
+use crate::db::with_transaction;
+#[cfg(feature = "json-store")]
+use crate::db::{connect_db_file, json_store::{json_path, JsonStore}, store::migrate_tasks};
+#[cfg(feature = "json-store")]
+use crate::profile::profile_database;
+use crate::db::user::{delete_user, insert_user, issue_token};
+use crate::gitsync::{git_sync, mirror_tasks, GIT_DIR_VAR};
+use crate::remind::{run_reminders, CommandSink, ReminderSink, StdoutSink};
+use crate::server::serve;
+use crate::shell::run_shell;
+use crate::tui::run_tui;
 
 use super::Args;
 
@@ -12,107 +42,844 @@ pub enum Commands {
         #[arg(short = 'n', long = "name")]
         name: String,
 
-        /// Dry run test
-        #[arg(short = 't', long = "dry-test", default_value_t = false)]
-        dry_test: bool,
+        /// When to be reminded, as YYYY-MM-DD HH:MM in local time
+        #[arg(short = 'r', long = "remind-at")]
+        remind_at: Option<String>,
     },
 
     /// Mark a task as done or undone
     Update {
-        /// Id of the task
-        #[arg(short = 'i', long = "id")]
-        id: u32,
+        /// Ids, @indexes or uuids of the tasks, or ranges such as 3-9
+        #[arg(short = 'i', long = "id", num_args = 1.., value_delimiter = ',', required_unless_present = "filter")]
+        ids: Vec<String>,
+
+        /// Only the tasks matching a filter such as "done=false,name=milk"
+        #[arg(short = 'w', long = "where")]
+        filter: Option<String>,
+
+        /// Set task to done or not done (yes/no, true/false, 1/0)
+        #[arg(short = 'd', long = "done", action = ArgAction::Set, value_parser = BoolishValueParser::new())]
+        done: bool,
+
+        /// Skip the confirmation asked when many tasks are affected
+        #[arg(short = 'y', long = "yes", default_value_t = false)]
+        yes: bool,
+    },
+
+    /// Mark tasks as done
+    Done {
+        /// Ids, @indexes or uuids of the tasks, or ranges such as 3-9
+        #[arg(value_delimiter = ',', required_unless_present = "filter")]
+        ids: Vec<String>,
 
-        /// Set task to done or not done
-        #[arg(short = 'd', long = "done")]
-        done: String,
+        /// Only the tasks matching a filter such as "name=milk"
+        #[arg(short = 'w', long = "where")]
+        filter: Option<String>,
 
-        /// Dry run test
-        #[arg(short = 't', long = "dry-test", default_value_t = false)]
-        dry_test: bool,
+        /// Skip the confirmation asked when many tasks are affected
+        #[arg(short = 'y', long = "yes", default_value_t = false)]
+        yes: bool,
+    },
+
+    /// Mark done tasks as not done again
+    Reopen {
+        /// Ids, @indexes or uuids of the tasks, or ranges such as 3-9
+        #[arg(value_delimiter = ',', required_unless_present = "filter")]
+        ids: Vec<String>,
+
+        /// Only the tasks matching a filter such as "before=2030-01-01"
+        #[arg(short = 'w', long = "where")]
+        filter: Option<String>,
+
+        /// Skip the confirmation asked when many tasks are affected
+        #[arg(short = 'y', long = "yes", default_value_t = false)]
+        yes: bool,
     },
 
     /// List tasks
     Get {
-        /// Dry run test
-        #[arg(short = 't', long = "dry-test", default_value_t = false)]
-        dry_test: bool,
+        /// Only list tasks completed on or after this date (YYYY-MM-DD)
+        #[arg(short = 'c', long = "completed-since")]
+        completed_since: Option<String>,
+
+        /// List every task changed after this UTC timestamp, deleted ones included
+        #[arg(long = "changed-since", conflicts_with = "completed_since")]
+        changed_since: Option<String>,
     },
 
-    /// Delete a task by id
+    /// Delete tasks by id
     Delete {
-        /// Id of the expense
+        /// Ids, @indexes or uuids of the tasks, or ranges such as 3-9
+        #[arg(value_delimiter = ',', required_unless_present = "filter")]
+        ids: Vec<String>,
+
+        /// Only the tasks matching a filter such as "done=true"
+        #[arg(short = 'w', long = "where")]
+        filter: Option<String>,
+
+        /// Skip the confirmation asked when many tasks are affected
+        #[arg(short = 'y', long = "yes", default_value_t = false)]
+        yes: bool,
+    },
+
+    /// Merge the tasks with another database file, both ways
+    Sync {
+        /// Other database file, e.g. on a shared drive
+        #[arg()]
+        other: String,
+    },
+
+    /// Commit the tasks to a git working directory, pull and merge the
+    /// remote changes and push the result
+    GitSync {
+        /// Git working directory holding the tasks file
+        #[arg()]
+        dir: String,
+    },
+
+    /// Copy every task from one storage to another, empty one
+    #[cfg(feature = "json-store")]
+    MigrateStorage {
+        /// Storage to copy the tasks from
+        #[arg(long = "from", value_enum)]
+        from: StorageKind,
+
+        /// Storage to copy the tasks to
+        #[arg(long = "to", value_enum)]
+        to: StorageKind,
+    },
+
+    /// Save a snapshot of the database to a file
+    Backup {
+        /// Destination file of the backup
+        #[arg()]
+        dest: String,
+    },
+
+    /// Replace the database with a previously saved backup
+    RestoreBackup {
+        /// Backup file to restore from
+        #[arg()]
+        src: String,
+    },
+
+    /// Serve the tasks over a local HTTP REST API
+    Serve {
+        /// Address to listen on
+        #[arg(short = 'b', long = "bind", default_value = "127.0.0.1:8080")]
+        bind: String,
+    },
+
+    /// Manage the users allowed to access the server
+    User {
+        #[command(subcommand)]
+        action: UserCommands,
+    },
+
+    /// Open the interactive full-screen task list
+    Tui,
+
+    /// Start an interactive shell accepting these same commands
+    Shell,
+
+    /// Watch the database and deliver reminders as they come due
+    Remind {
+        /// Seconds between two polls of the database
+        #[arg(short = 'i', long = "interval", default_value_t = 60)]
+        interval: u64,
+
+        /// Shell command run for each reminder, with TODO_TASK_ID, TODO_TASK_NAME and TODO_REMIND_AT set
+        #[arg(short = 'e', long = "exec")]
+        exec: Option<String>,
+
+        /// Unix socket receiving one JSON line per reminder
+        #[arg(short = 's', long = "socket")]
+        socket: Option<String>,
+
+        /// Poll once and exit instead of running forever
+        #[arg(short = 'o', long = "once", default_value_t = false)]
+        once: bool,
+    },
+
+    /// Set or clear the reminder of a task
+    Reminder {
+        /// Id, @index or uuid of the task
+        #[arg()]
+        id: String,
+
+        /// When to be reminded, as YYYY-MM-DD HH:MM in local time
+        #[arg(short = 'r', long = "remind-at", required_unless_present = "clear", conflicts_with = "clear")]
+        remind_at: Option<String>,
+
+        /// Remove the reminder instead
+        #[arg(short = 'c', long = "clear", default_value_t = false)]
+        clear: bool,
+    },
+
+    /// Start tracking time on a task
+    Start {
+        /// Id, @index or uuid of the task
+        #[arg()]
+        id: String,
+    },
+
+    /// Stop the running timer
+    Stop,
+
+    /// Show the time tracked on a task
+    Time {
+        /// Id, @index or uuid of the task
         #[arg()]
-        id: u32,
+        id: String,
+    },
+
+    /// Summarize tracked hours per task and per day
+    Report {
+        /// First day of the report, as YYYY-MM-DD (defaults to 7 days ago)
+        #[arg(short = 's', long = "since")]
+        since: Option<String>,
+    },
+
+    /// Show completion statistics
+    Stats,
+
+    /// Print a shell completion script
+    Completions {
+        /// Shell to generate the script for
+        #[arg(value_enum)]
+        shell: CompletionShell,
+    },
+
+    /// List live task ids for the completion scripts
+    #[command(name = "__complete-ids", hide = true)]
+    CompleteIds,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum UserCommands {
+    /// Create a user and print its API token
+    Add {
+        /// User's name
+        #[arg()]
+        name: String,
+    },
+
+    /// Remove a user, its tasks become local only
+    Remove {
+        /// User's name
+        #[arg()]
+        name: String,
+    },
+
+    /// Issue a new API token for a user, revoking the previous one
+    Token {
+        /// User's name
+        #[arg()]
+        name: String,
+    },
+}
+
+/// The storages `migrate-storage` converts between.
+#[cfg(feature = "json-store")]
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum StorageKind {
+  /// tasks.db
+  Sqlite,
+  /// tasks.json
+  Json,
+}
+
+#[cfg(feature = "json-store")]
+fn open_storage(kind: StorageKind, database: &Path) -> Result<Box<dyn TaskStore>, CommandError> {
+  match kind {
+    StorageKind::Sqlite => connect_db_file(database)
+      .map(|conn| Box::new(conn) as Box<dyn TaskStore>)
+      .map_err(database_error("Error opening the SQLite storage")),
+    StorageKind::Json => JsonStore::open_exclusive(json_path(database))
+      .map(|store| Box::new(store) as Box<dyn TaskStore>)
+      .map_err(io_error("Error opening the JSON storage")),
+  }
+}
+
+/// The `kind` storage of `database` for `migrate-storage`, or `None` when
+/// `store` is that storage, already open and locked for the command.
+#[cfg(feature = "json-store")]
+fn open_other_storage(store: &dyn TaskStore, kind: StorageKind, database: &Path) -> Result<Option<Box<dyn TaskStore>>, CommandError> {
+  let active = match kind {
+    StorageKind::Sqlite => store.connection().is_some(),
+    StorageKind::Json => (store as &dyn std::any::Any).is::<JsonStore>(),
+  };
+  if active {
+    return Ok(None);
+  }
+
+  open_storage(kind, database).map(Some)
+}
+
+/// What a command did, for a renderer to show: the tasks it affected or
+/// listed, informational messages and warnings about parts that failed
+/// without failing the command.
+#[derive(Serialize, Debug, Default)]
+pub struct CommandOutcome {
+  pub tasks: Vec<Task>,
+  pub messages: Vec<String>,
+  pub warnings: Vec<String>,
+}
+
+impl CommandOutcome {
+  fn message(&mut self, message: impl Into<String>) {
+    self.messages.push(message.into());
+  }
+
+  fn warning(&mut self, warning: impl Into<String>) {
+    self.warnings.push(warning.into());
+  }
+}
+
+/// Why a command failed. Each kind exits the command line with its own code,
+/// so scripts can tell them apart:
+///
+/// | Code | Kind |
+/// |------|------|
+/// | 0 | success |
+/// | 1 | `Usage`: malformed arguments, or a command the storage does not support |
+/// | 2 | `NotFound`: a task, user or timer the command needs does not exist |
+/// | 3 | `Invalid`: an id, date, filter or name was rejected |
+/// | 4 | `Storage`: the database or another file could not be read or written |
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandError {
+  Usage(String),
+  NotFound(String),
+  Invalid(String),
+  Storage(String),
+}
+
+impl CommandError {
+  /// Exit code of the process when the command fails this way.
+  pub fn exit_code(&self) -> i32 {
+    match self {
+      CommandError::Usage(_) => 1,
+      CommandError::NotFound(_) => 2,
+      CommandError::Invalid(_) => 3,
+      CommandError::Storage(_) => 4,
+    }
+  }
+}
+
+impl fmt::Display for CommandError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      CommandError::Usage(message) | CommandError::NotFound(message)
+      | CommandError::Invalid(message) | CommandError::Storage(message) => write!(f, "{message}"),
+    }
+  }
+}
+
+/// Bulk commands affecting more tasks than this ask for confirmation.
+pub const CONFIRM_THRESHOLD: usize = 5;
+
+fn describe_selection(ids: &[String], filter: Option<&str>) -> String {
+  match filter {
+    Some(filter) if ids.is_empty() => format!("where {filter}"),
+    Some(filter) => format!("{} where {filter}", ids.join(" ")),
+    None => ids.join(" "),
+  }
+}
+
+/// Expand the ids, indexes, uuids, ranges and `--where` filter of a bulk command.
+fn resolve_selection(store: &dyn TaskStore, ids: &[String], filter: Option<&str>) -> StoreResult<Vec<u32>> {
+  let ids = store.resolve(ids)?;
+  let filter = filter.map(parse_filter).transpose()?;
+  store.select(&ids, filter.as_ref())
+}
+
+/// Preview how many tasks `action` affects and, above `CONFIRM_THRESHOLD`,
+/// ask on the terminal unless `yes` is set. End of input means no.
+fn confirm_bulk(action: &str, count: usize, yes: bool) -> bool {
+  if count <= CONFIRM_THRESHOLD || yes {
+    info!("{action} affects {count} task(s)");
+    return true;
+  }
+
+  // The question cannot wait for the outcome to be rendered
+  print!("{action} affects {count} task(s). Continue? [y/N] ");
+  let _ = io::stdout().flush();
+  let mut answer = String::new();
+  match io::stdin().read_line(&mut answer) {
+    Ok(_) => matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"),
+    Err(_) => false,
+  }
+}
+
+/// Message for a failed selection: unknown tasks and invalid ids or
+/// filters are reported as they are.
+fn selection_error(err: impl Into<StoreError>) -> CommandError {
+  match err.into() {
+    StoreError::NotFound => CommandError::NotFound("Task not found".to_string()),
+    StoreError::Invalid(message) => CommandError::Invalid(message),
+    err => CommandError::Storage(format!("Error selecting tasks: {}", err)),
+  }
+}
+
+/// Message for a failed storage operation: rejected input is reported as
+/// it is, anything else after what was being done.
+fn database_error<E: Into<StoreError>>(action: &'static str) -> impl FnOnce(E) -> CommandError {
+  move |err| match err.into() {
+    StoreError::Invalid(message) => CommandError::Invalid(message),
+    err => CommandError::Storage(format!("{action}: {err}")),
+  }
+}
+
+/// Message for a failed file or network operation, after what was being done.
+fn io_error(action: &'static str) -> impl FnOnce(io::Error) -> CommandError {
+  move |err| CommandError::Storage(format!("{action}: {err}"))
+}
+
+/// Message for a failed user operation on the user `name`.
+fn user_error<'a>(name: &'a str, action: &'static str) -> impl FnOnce(rusqlite::Error) -> CommandError + 'a {
+  move |err| match err {
+    rusqlite::Error::QueryReturnedNoRows => CommandError::NotFound(format!("User {name} not found")),
+    rusqlite::Error::SqliteFailure(failure, _) if failure.code == rusqlite::ErrorCode::ConstraintViolation =>
+      CommandError::Invalid(format!("User {name} already exists")),
+    err => database_error(action)(err),
+  }
+}
+
+fn invalid_date(since: &str) -> CommandError {
+  CommandError::Invalid(format!("Invalid date '{since}', expected YYYY-MM-DD"))
+}
+
+/// The action, ids, `--where` filter and `--yes` of a bulk command, whose
+/// selection is confirmed before its transaction begins.
+fn bulk_selection(command: &Commands) -> Option<(&'static str, &[String], Option<&str>, bool)> {
+  match command {
+    Commands::Update { ids, filter, done, yes } => Some((if *done { "Done" } else { "Reopen" }, ids, filter.as_deref(), *yes)),
+    Commands::Done { ids, filter, yes } => Some(("Done", ids, filter.as_deref(), *yes)),
+    Commands::Reopen { ids, filter, yes } => Some(("Reopen", ids, filter.as_deref(), *yes)),
+    Commands::Delete { ids, filter, yes } => Some(("Delete", ids, filter.as_deref(), *yes)),
+    _ => None,
+  }
+}
+
+/// Resolve the selection of a bulk command inside its transaction. When
+/// the user was asked about `confirmed`, it must still be the same tasks.
+fn confirmed_selection(store: &dyn TaskStore, ids: &[String], filter: Option<&str>, confirmed: Option<Vec<u32>>) -> Result<Vec<u32>, CommandError> {
+  let ids = resolve_selection(store, ids, filter).map_err(selection_error)?;
+  match confirmed {
+    Some(confirmed) if confirmed != ids => Err(CommandError::Invalid("The selected tasks changed while asking, nothing was done".to_string())),
+    _ => Ok(ids),
+  }
+}
+
+/// Set the status of the selected tasks, for `update`, `done` and `reopen`.
+fn set_tasks_status(store: &dyn TaskStore, outcome: &mut CommandOutcome, ids: &[u32], done: bool) -> Result<(), CommandError> {
+  let completed_by = env::var("USER").ok();
+  let tasks = store.update(ids, done, completed_by.as_deref())
+    .map_err(database_error("Error updating tasks"))?;
+  outcome.tasks.extend(tasks);
+
+  Ok(())
+}
+
+/// The SQLite connection of `store`, for the commands that need more than
+/// the tasks.
+fn sqlite<'a>(store: &'a dyn TaskStore, command: &str) -> Result<&'a Connection, CommandError> {
+  store.connection().ok_or_else(|| CommandError::Usage(format!("The {command} command needs the SQLite storage")))
+}
+
+fn take_rotating_backup(conn: &Connection, outcome: &mut CommandOutcome) {
+  match rotate_backups(conn) {
+    Ok(Some(path)) => info!("Automatic backup saved to {}", path.display()),
+    Ok(None) => {},
+    Err(err) => outcome.warning(format!("Error taking automatic backup: {}", err)),
+  }
+}
+
+/// Commands that change the database briefly. On SQLite they run in a
+/// single transaction, so parallel invocations wait for each other instead
+/// of interleaving; long running and file level commands manage their own.
+fn runs_in_transaction(command: &Commands) -> bool {
+  matches!(command,
+    Commands::Add { .. } | Commands::Update { .. } | Commands::Done { .. } | Commands::Reopen { .. }
+    | Commands::Delete { .. } | Commands::User { .. } | Commands::Reminder { .. } | Commands::Start { .. }
+    | Commands::Stop)
+}
+
+/// Commands that only read the database. They run without a transaction,
+/// so they never wait for the write lock.
+fn reads_only(command: &Commands) -> bool {
+  matches!(command,
+    Commands::Get { .. } | Commands::Time { .. } | Commands::Report { .. } | Commands::Stats | Commands::CompleteIds)
+}
 
-        /// Dry run test
-        #[arg(short = 't', long = "dry-test", default_value_t = false)]
-        dry_test: bool,
+/// Commands whose changes are mirrored to the git directory, every one but
+/// the reads and those writing outside the database.
+fn changes_tasks(command: &Commands) -> bool {
+  !reads_only(command) && !matches!(command, Commands::Completions { .. } | Commands::Backup { .. })
+}
+
+/// Commands accepting `--dry-run`: the ones running in one transaction,
+/// rolled back at the end, the reads, `sync`, which only compares the two
+/// databases, and `completions`, which writes nothing anyway.
+fn supports_dry_run(command: &Commands) -> bool {
+  runs_in_transaction(command) || reads_only(command)
+    || matches!(command, Commands::Sync { .. } | Commands::Completions { .. })
+}
+
+/// Mirror the tasks to the directory in `GIT_DIR_VAR`, when it is set.
+fn mirror_changes(store: &dyn TaskStore, outcome: &mut CommandOutcome) {
+  if let (Ok(dir), Some(conn)) = (env::var(GIT_DIR_VAR), store.connection()) {
+    if let Err(err) = mirror_tasks(conn, Path::new(&dir)) {
+      outcome.warning(format!("Error mirroring tasks to {dir}: {err}"));
+    }
+  }
+}
+
+/// Run a parsed command and return what it did, without printing it; see
+/// `crate::render` for showing the outcome. Failures, such as unknown tasks
+/// or malformed ids, dates and filters, are returned as a `CommandError` so
+/// the caller can report them and exit with its code; the changes of a
+/// failed command are rolled back, as are those of every command run with
+/// `--dry-run`, whose outcome shows what it would have changed. Tasks go
+/// through `store`; the other commands need it to be a SQLite connection.
+/// Interactive commands, like `shell` or `serve`, still talk to the terminal
+/// while they run.
+pub fn command_switch(args: Args, store: &dyn TaskStore) -> Result<CommandOutcome, CommandError> {
+  debug!("Running {:?}", args.cmd);
+  let dry_run = args.options.dry_run;
+  if dry_run && !supports_dry_run(&args.cmd) {
+    return Err(CommandError::Usage("This command does not support --dry-run".to_string()));
+  }
+  if dry_run && store.connection().is_none() && (runs_in_transaction(&args.cmd) || reads_only(&args.cmd)) {
+    return Err(CommandError::Usage("--dry-run needs the SQLite storage".to_string()));
+  }
+
+  // Asking before the transaction keeps the database unlocked while waiting for an answer
+  let mut confirmed = None;
+  if let Some((action, ids, filter, yes)) = bulk_selection(&args.cmd) {
+    let ids = resolve_selection(store, ids, filter).map_err(selection_error)?;
+    if !confirm_bulk(action, ids.len(), yes) {
+      let mut outcome = CommandOutcome::default();
+      outcome.message("Aborted");
+      return Ok(outcome);
+    }
+    if ids.len() > CONFIRM_THRESHOLD && !yes {
+      confirmed = Some(ids);
+    }
+  }
+
+  let (reads, changes) = (reads_only(&args.cmd), changes_tasks(&args.cmd));
+  let deletes = matches!(args.cmd, Commands::Delete { .. });
+  let conn = match store.connection() {
+    Some(conn) if runs_in_transaction(&args.cmd) => conn,
+    _ => {
+      let mut outcome = dispatch(args, store, confirmed)?;
+      if dry_run && reads {
+        outcome.message("Dry run, nothing was saved");
+      } else if changes && !dry_run {
+        mirror_changes(store, &mut outcome);
+      }
+      return Ok(outcome);
     },
+  };
+
+  // Before the transaction, so the backup still holds what is deleted
+  let mut backup = CommandOutcome::default();
+  if deletes && !dry_run {
+    take_rotating_backup(conn, &mut backup);
+  }
+
+  let mut outcome = None;
+  let saved = with_transaction(conn, |_| {
+    let result = dispatch(args, store, confirmed);
+    let keep = result.is_ok() && !dry_run;
+    outcome = Some(result);
+    // Errors and dry runs roll the transaction back, the outcome is kept in `outcome`
+    if keep { Ok(()) } else { Err(rusqlite::Error::InvalidQuery) }
+  });
+  let Some(outcome) = outcome else {
+    // Without an outcome the transaction could not even begin
+    return Err(database_error("Error starting transaction")(saved.err().unwrap_or(rusqlite::Error::InvalidQuery)));
+  };
+  let mut outcome = outcome.inspect_err(|_| debug!("Rolled back the changes of the failed command"))?;
+  outcome.warnings.splice(0..0, backup.warnings);
+  if dry_run {
+    outcome.message("Dry run, nothing was saved");
+    return Ok(outcome);
+  }
+  saved.map_err(database_error("Error saving changes"))?;
+
+  mirror_changes(store, &mut outcome);
+
+  Ok(outcome)
 }
 
-pub fn command_switch(args: Args, conn: &Connection) {
+fn dispatch(args: Args, store: &dyn TaskStore, confirmed: Option<Vec<u32>>) -> Result<CommandOutcome, CommandError> {
+    let mut outcome = CommandOutcome::default();
+    let dry_run = args.options.dry_run;
+
     match args.cmd {
-      Commands::Add { name, dry_test } => {
-        println!("Add task {name} {0}", match dry_test{
-          true => "in dry run mode",
-          false => "in normal mode"
-        });
-        insert_task(conn, name).unwrap();
-      },
-      Commands::Update { id, done, dry_test } => {
-        println!("Update task {id} with done status: {done} {0}", match dry_test{
-          true => "in dry run mode",
-          false => "in normal mode"
-        });
-        let done = match done.to_lowercase().as_str() {
-          "true" => true,
-          "false" => false,
-          _ => {
-            eprintln!("Invalid value for done status. Expected 'true' or 'false'");
-            return;
+      Commands::Add { name, remind_at } => {
+        info!("Add task {name}");
+        if let Some(remind_at) = &remind_at {
+          parse_remind_at(remind_at).map_err(selection_error)?;
+          sqlite(store, "add --remind-at")?;
+        }
+        let task = store.insert(name).map_err(selection_error)?;
+        if let Some(remind_at) = remind_at {
+          match set_task_reminder(sqlite(store, "add --remind-at")?, task.id, &remind_at) {
+            Ok(()) => outcome.message(format!("Reminder set for task {}", task.id)),
+            Err(err) => outcome.warning(format!("Error setting reminder: {}", err)),
           }
+        }
+        outcome.tasks.push(task);
+      },
+      Commands::Update { ids, filter, done, .. } => {
+        info!("Update task {} with done status: {done}", describe_selection(&ids, filter.as_deref()));
+        let ids = confirmed_selection(store, &ids, filter.as_deref(), confirmed)?;
+        set_tasks_status(store, &mut outcome, &ids, done)?;
+      },
+      Commands::Done { ids, filter, .. } => {
+        info!("Complete task {}", describe_selection(&ids, filter.as_deref()));
+        let ids = confirmed_selection(store, &ids, filter.as_deref(), confirmed)?;
+        set_tasks_status(store, &mut outcome, &ids, true)?;
+      },
+      Commands::Reopen { ids, filter, .. } => {
+        info!("Reopen task {}", describe_selection(&ids, filter.as_deref()));
+        let ids = confirmed_selection(store, &ids, filter.as_deref(), confirmed)?;
+        set_tasks_status(store, &mut outcome, &ids, false)?;
+      },
+      Commands::Get { completed_since, changed_since: changed_after } => {
+        info!("List tasks");
+        let tasks = match (completed_since, changed_after) {
+          (_, Some(since)) => store.changed_since(&since),
+          (Some(since), _) => match NaiveDate::parse_from_str(&since, "%Y-%m-%d") {
+            Ok(date) => store.completed_since(&date.format("%Y-%m-%d").to_string()),
+            Err(_) => return Err(invalid_date(&since)),
+          },
+          (None, None) => store.list(),
         };
-        let updated_task = update_task_status(conn, id, done).unwrap();
-        updated_task.log();
-      },
-      Commands::Get { dry_test } => {
-        println!("List tasks {0}", match dry_test{
-          true => "in dry run mode",
-          false => "in normal mode"
-        });
-        let tasks = read_tasks(conn).unwrap();
-        for task in tasks {
-          task.log();
+        outcome.tasks = tasks.map_err(database_error("Error reading tasks"))?;
+      },
+      Commands::Delete { ids, filter, .. } => {
+        info!("Delete task {}", describe_selection(&ids, filter.as_deref()));
+        let ids = confirmed_selection(store, &ids, filter.as_deref(), confirmed)?;
+        let tasks = store.delete(&ids).map_err(database_error("Error deleting tasks"))?;
+        outcome.message(format!("{} task(s) deleted successfully", tasks.len()));
+        outcome.tasks = tasks;
+      },
+      Commands::Sync { other } => {
+        info!("Sync with {other}");
+        let conn = sqlite(store, "sync")?;
+        let other_conn = open_other_db(&other).map_err(|err| CommandError::Storage(format!("Error opening {other}: {err}")))?;
+        if !dry_run {
+          take_rotating_backup(conn, &mut outcome);
         }
+        let report = sync_databases(conn, &other_conn, dry_run).map_err(database_error("Error syncing"))?;
+        outcome.message(format!("{} {other}:", if dry_run { "Would pull from" } else { "Pulled from" }));
+        outcome.messages.extend(report.into_local.iter().map(ToString::to_string));
+        outcome.message(format!("{} {other}:", if dry_run { "Would push to" } else { "Pushed to" }));
+        outcome.messages.extend(report.into_other.iter().map(ToString::to_string));
+        outcome.message("Conflicts:");
+        outcome.messages.extend(report.conflicts.iter().map(ToString::to_string));
       },
-      Commands::Delete { id, dry_test } => {
-        println!("Delete task {id} with id {0}", match dry_test{
-          true => "in dry run mode",
-          false => "in normal mode"
-        });
-        match delete_task(conn, id) {
-          Ok(task) => {
-            println!("Task deleted successfully");
-            task.log();
-          }
-          Err(err) => {
-            eprintln!("Error deleting task: {}", err);
-          }
+      Commands::GitSync { dir } => {
+        info!("Git sync with {dir}");
+        let conn = sqlite(store, "git-sync")?;
+        take_rotating_backup(conn, &mut outcome);
+        let report = git_sync(conn, Path::new(&dir)).map_err(io_error("Error syncing with git"))?;
+        outcome.message("Pulled:");
+        outcome.messages.extend(report.into_local.iter().map(ToString::to_string));
+        outcome.message("Conflicts:");
+        outcome.messages.extend(report.conflicts.iter().map(ToString::to_string));
+        if report.pushed {
+          outcome.message("Pushed to origin");
+        }
+      },
+      #[cfg(feature = "json-store")]
+      Commands::MigrateStorage { from, to } => {
+        let name = |kind: StorageKind| kind.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default();
+        info!("Migrate tasks from {} to {}", name(from), name(to));
+        if from == to {
+          return Err(CommandError::Usage("Nothing to migrate, both storages are the same".to_string()));
+        }
+        let database = profile_database(args.options.profile_name())?;
+        let (source, destination) = (open_other_storage(store, from, &database)?, open_other_storage(store, to, &database)?);
+        let count = migrate_tasks(source.as_deref().unwrap_or(store), destination.as_deref().unwrap_or(store))
+          .map_err(database_error("Error migrating tasks"))?;
+        outcome.message(format!("{count} task(s) migrated successfully"));
+      },
+      Commands::Backup { dest } => {
+        info!("Backup database to {dest}");
+        backup_db(sqlite(store, "backup")?, &dest).map_err(database_error("Error saving backup"))?;
+        outcome.message("Backup saved successfully");
+      },
+      Commands::RestoreBackup { src } => {
+        info!("Restore database from {src}");
+        let conn = sqlite(store, "restore-backup")?;
+        take_rotating_backup(conn, &mut outcome);
+        restore_db(conn, &src).map_err(database_error("Error restoring backup"))?;
+        outcome.message("Backup restored successfully");
+      },
+      Commands::Serve { bind } => {
+        let conn = sqlite(store, "serve")?;
+        info!("Serving tasks on http://{bind}");
+        serve(conn, &bind).map_err(|err| CommandError::Storage(format!("Error starting server: {err}")))?;
+      },
+      Commands::User { action } => {
+        let conn = sqlite(store, "user")?;
+        match action {
+          UserCommands::Add { name } => {
+            info!("Add user {name}");
+            let (user, token) = insert_user(conn, name.clone()).map_err(user_error(&name, "Error adding user"))?;
+            outcome.message(user.to_string());
+            outcome.message(format!("token = {token}"));
+          },
+          UserCommands::Remove { name } => {
+            info!("Remove user {name}");
+            let user = delete_user(conn, &name).map_err(user_error(&name, "Error removing user"))?;
+            outcome.message("User removed successfully");
+            outcome.message(user.to_string());
+          },
+          UserCommands::Token { name } => {
+            info!("Issue token for user {name}");
+            let token = issue_token(conn, &name).map_err(user_error(&name, "Error issuing token"))?;
+            outcome.message(format!("token = {token}"));
+          },
+        }
+      },
+      Commands::Tui => {
+        run_tui(sqlite(store, "tui")?).map_err(io_error("Error running the terminal UI"))?;
+      },
+      Commands::Shell => {
+        let conn = sqlite(store, "shell")?;
+        info!("Interactive shell, type exit to leave");
+        run_shell(conn).map_err(|err| CommandError::Storage(format!("Error running the shell: {err}")))?;
+      },
+      Commands::Remind { interval, exec, socket, once } => {
+        let conn = sqlite(store, "remind")?;
+        info!("Watching reminders");
+        let mut sinks: Vec<Box<dyn ReminderSink>> = vec![Box::new(StdoutSink)];
+        if let Some(command) = exec {
+          sinks.push(Box::new(CommandSink { command }));
+        }
+        if let Some(path) = socket {
+          #[cfg(unix)]
+          sinks.push(Box::new(crate::remind::UnixSocketSink { path }));
+          #[cfg(not(unix))]
+          outcome.warning(format!("Unix sockets are not supported on this platform, ignoring {path}"));
         }
+        run_reminders(conn, sinks, std::time::Duration::from_secs(interval), once)
+          .map_err(database_error("Error watching reminders"))?;
+      },
+      Commands::Reminder { id, remind_at, clear } => {
+        info!("Set reminder of task {id}");
+        let conn = sqlite(store, "reminder")?;
+        let id = resolve_task_ref(conn, &id).map_err(selection_error)?;
+        match remind_at.filter(|_| !clear) {
+          Some(remind_at) => {
+            set_task_reminder(conn, id, &remind_at).map_err(selection_error)?;
+            let remind_at = parse_remind_at(&remind_at).map_err(selection_error)?;
+            outcome.message(format!("Reminder set for {}", local_remind_at(&remind_at)));
+          },
+          None => {
+            clear_task_reminder(conn, id).map_err(selection_error)?;
+            outcome.message("Reminder cleared");
+          },
+        }
+        outcome.tasks.push(store.get(id).map_err(selection_error)?);
+      },
+      Commands::Start { id } => {
+        info!("Start timer on task {id}");
+        let conn = sqlite(store, "start")?;
+        let id = resolve_task_ref(conn, &id).map_err(selection_error)?;
+        let entry = start_timer(conn, id, Local::now().naive_local()).map_err(database_error("Error starting timer"))?;
+        outcome.message(entry.to_string());
+      },
+      Commands::Stop => {
+        info!("Stop timer");
+        let entry = stop_timer(sqlite(store, "stop")?, Local::now().naive_local()).map_err(|err| match err {
+          rusqlite::Error::QueryReturnedNoRows => CommandError::NotFound("No timer is running".to_string()),
+          err => database_error("Error stopping timer")(err),
+        })?;
+        outcome.message(entry.to_string());
+      },
+      Commands::Time { id } => {
+        info!("Time tracked on task {id}");
+        let conn = sqlite(store, "time")?;
+        let id = resolve_task_ref(conn, &id).map_err(selection_error)?;
+        let duration = task_duration(conn, id, Local::now().naive_local()).map_err(database_error("Error reading tracked time"))?;
+        outcome.message(format!("total = {}", format_duration(duration)));
+      },
+      Commands::Report { since } => {
+        info!("Time report");
+        let now = Local::now().naive_local();
+        let since = match since {
+          Some(since) => match NaiveDate::parse_from_str(&since, "%Y-%m-%d") {
+            Ok(date) => date,
+            Err(_) => return Err(invalid_date(&since)),
+          },
+          None => now.date() - Duration::days(7),
+        };
+        let report = time_report(sqlite(store, "report")?, since.and_hms_opt(0, 0, 0).unwrap(), now)
+          .map_err(database_error("Error building report"))?;
+        outcome.message("Per task:");
+        for (id, name, duration) in report.per_task {
+          outcome.message(format!("id = {}, name = {}, total = {}", id, name, format_duration(duration)));
+        }
+        outcome.message("Per day:");
+        for (day, duration) in report.per_day {
+          outcome.message(format!("day = {}, total = {}", day, format_duration(duration)));
+        }
+      },
+      Commands::Stats => {
+        info!("Task statistics");
+        let stats = compute_stats(sqlite(store, "stats")?).map_err(database_error("Error computing statistics"))?;
+        outcome.message(format!("total = {}, open = {}, done = {}, completion_rate = {:.1}%",
+                                stats.total, stats.open, stats.done, stats.completion_rate * 100.0));
+        match stats.average_completion_seconds {
+          Some(seconds) => outcome.message(format!("average time to complete = {}", format_seconds(seconds))),
+          None => outcome.message("average time to complete = n/a"),
+        }
+        outcome.message("Completed per day:");
+        for (day, count) in stats.completed_per_day {
+          outcome.message(format!("day = {}, done = {}", day, count));
+        }
+        outcome.message("Completed per week:");
+        for (week, count) in stats.completed_per_week {
+          outcome.message(format!("week = {}, done = {}", week, count));
+        }
+        // The tasks are rendered after the messages, under this heading
+        outcome.message("Oldest open tasks:");
+        outcome.tasks = stats.oldest_open;
+      },
+      Commands::Completions { shell } => {
+        let mut script = Vec::new();
+        write_completions(shell, &mut script).map_err(io_error("Error writing completions"))?;
+        outcome.message(String::from_utf8_lossy(&script).trim_end());
+      },
+      Commands::CompleteIds => {
+        let mut ids = Vec::new();
+        write_task_ids(sqlite(store, "complete-ids")?, &mut ids).map_err(database_error("Error listing task ids"))?;
+        outcome.messages.extend(String::from_utf8_lossy(&ids).lines().map(String::from));
       }
     }
+
+    Ok(outcome)
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
-  use super::super::connect_db;
+  use super::super::{connect_db, GlobalOptions};
+  use crate::db::store::MemoryStore;
+  use crate::db::task::{insert_task, read_tasks};
+  use clap::Parser;
   use rusqlite::Result;
 
   fn setup() -> Result<Connection>{
     // Set up test environment
-    return connect_db(Some(true), None);
+    connect_db(Some(true), None)
   }
 
   #[test]
@@ -122,16 +889,17 @@ mod tests {
     let args = Args {
       cmd: Commands::Add {
         name: String::from("Test Task"),
-        dry_test: false,
+        remind_at: None,
       },
+      options: GlobalOptions::default(),
     };
     
-    command_switch(args, &conn);
+    command_switch(args, &conn).unwrap();
     
     let tasks = read_tasks(&conn).unwrap();
 
     assert!(
-      tasks.len() == 1 && tasks[0].name == String::from("Test Task"),
+      tasks.len() == 1 && tasks[0].name == "Test Task",
       "The task was not added to the database"
     );
   }
@@ -143,29 +911,32 @@ mod tests {
     let args = Args {
       cmd: Commands::Add {
         name: String::from("Test Task"),
-        dry_test: false,
+        remind_at: None,
       },
+      options: GlobalOptions::default(),
     };
     
-    command_switch(args, &conn);
+    command_switch(args, &conn).unwrap();
     
     let tasks = read_tasks(&conn).unwrap();
     let task = &tasks[0];
 
     let args = Args {
       cmd: Commands::Update {
-        id: task.id,
-        done: String::from("true"),
-        dry_test: false,
+        ids: vec![task.id.to_string()],
+        filter: None,
+        done: true,
+        yes: false,
       },
+      options: GlobalOptions::default(),
     };
     
-    command_switch(args, &conn);
+    command_switch(args, &conn).unwrap();
     
     let updated_task = read_tasks(&conn).unwrap().into_iter().find(|t| t.id == task.id).unwrap();
 
     assert!(
-      updated_task.is_done == true,
+      updated_task.is_done,
       "The task status was not updated"
     );
   }
@@ -177,46 +948,51 @@ mod tests {
     let args = Args {
       cmd: Commands::Add {
         name: String::from("Test Task"),
-        dry_test: false,
+        remind_at: None,
       },
+      options: GlobalOptions::default(),
     };
     
-    command_switch(args, &conn);
+    command_switch(args, &conn).unwrap();
     
     let tasks = read_tasks(&conn).unwrap();
     let task = &tasks[0];
 
     let args = Args {
       cmd: Commands::Update {
-        id: task.id,
-        done: String::from("true"),
-        dry_test: false,
+        ids: vec![task.id.to_string()],
+        filter: None,
+        done: true,
+        yes: false,
       },
+      options: GlobalOptions::default(),
     };
 
-    command_switch(args, &conn);
+    command_switch(args, &conn).unwrap();
 
     let updated_task = read_tasks(&conn).unwrap().into_iter().find(|t| t.id == task.id).unwrap();
 
     assert!(
-      updated_task.is_done == true,
+      updated_task.is_done,
       "The task status was not updated to true"
     );
     
     let args = Args {
       cmd: Commands::Update {
-        id: task.id,
-        done: String::from("false"),
-        dry_test: false,
+        ids: vec![task.id.to_string()],
+        filter: None,
+        done: false,
+        yes: false,
       },
+      options: GlobalOptions::default(),
     };
     
-    command_switch(args, &conn);
+    command_switch(args, &conn).unwrap();
     
     let updated_task = read_tasks(&conn).unwrap().into_iter().find(|t| t.id == task.id).unwrap();
 
     assert!(
-      updated_task.is_done == false,
+      !updated_task.is_done,
       "The task status was not updated to false"
     );
   }
@@ -228,29 +1004,22 @@ mod tests {
     let args = Args {
       cmd: Commands::Add {
         name: String::from("Test Task"),
-        dry_test: false,
+        remind_at: None,
       },
+      options: GlobalOptions::default(),
     };
     
-    command_switch(args, &conn);
+    command_switch(args, &conn).unwrap();
     
-    let tasks = read_tasks(&conn).unwrap();
-    let task = &tasks[0];
-
-    let args = Args {
-      cmd: Commands::Update {
-        id: task.id,
-        done: String::from("invalid"),
-        dry_test: false,
-      },
-    };
-    
-    command_switch(args, &conn);
+    assert!(
+      Args::try_parse_from(["todo", "update", "--id", "1", "--done", "invalid"]).is_err(),
+      "An invalid done value should be rejected"
+    );
     
     let tasks = read_tasks(&conn).unwrap();
 
     assert!(
-      tasks.len() == 1 && tasks[0].is_done == false,
+      tasks.len() == 1 && !tasks[0].is_done,
       "The task status was not updated to false with invalid input"
     );
   }
@@ -261,17 +1030,19 @@ mod tests {
       
     let args = Args {
       cmd: Commands::Delete {
-        id: 1,
-        dry_test: false,
+        ids: vec![String::from("1")],
+        filter: None,
+        yes: false,
       },
+      options: GlobalOptions::default(),
     };
     
-    command_switch(args, &conn);
+    assert_eq!(command_switch(args, &conn).unwrap_err(), CommandError::NotFound(String::from("Task not found")));
     
     let tasks = read_tasks(&conn).unwrap();
 
     assert!(
-      tasks.len() == 0,
+      tasks.is_empty(),
       "The non-existent task was not deleted from the database"
     );
   }
@@ -283,32 +1054,102 @@ mod tests {
     let args = Args {
       cmd: Commands::Add {
         name: String::from("Test Task"),
-        dry_test: false,
+        remind_at: None,
       },
+      options: GlobalOptions::default(),
     };
     
-    command_switch(args, &conn);
+    command_switch(args, &conn).unwrap();
     
     let tasks = read_tasks(&conn).unwrap();
     let task = &tasks[0];
 
     let args = Args {
       cmd: Commands::Delete {
-        id: task.id,
-        dry_test: false,
+        ids: vec![task.id.to_string()],
+        filter: None,
+        yes: false,
       },
+      options: GlobalOptions::default(),
     };
     
-    command_switch(args, &conn);
+    command_switch(args, &conn).unwrap();
     
     let tasks = read_tasks(&conn).unwrap();
 
     assert!(
-      tasks.len() == 0,
+      tasks.is_empty(),
       "The task was not deleted from the database"
     );
   }
 
+  #[test]
+  fn test_command_update_ids_and_ranges() {
+    let conn = setup().unwrap();
+    for index in 1..=CONFIRM_THRESHOLD + 2 {
+      insert_task(&conn, format!("Task {index}")).unwrap();
+    }
+
+    let args = Args {
+      cmd: Commands::Update {
+        ids: vec![String::from("1"), String::from("3-7")],
+        filter: None,
+        done: true,
+        yes: true,
+      },
+      options: GlobalOptions::default(),
+    };
+
+    command_switch(args, &conn).unwrap();
+
+    let done: Vec<u32> = read_tasks(&conn).unwrap().into_iter().filter(|t| t.is_done).map(|t| t.id).collect();
+    assert_eq!(done, vec![1, 3, 4, 5, 6, 7]);
+  }
+
+  #[test]
+  fn test_command_delete_where_filter() {
+    let conn = setup().unwrap();
+    insert_task(&conn, String::from("Buy milk")).unwrap();
+    insert_task(&conn, String::from("Buy bread")).unwrap();
+    insert_task(&conn, String::from("Write report")).unwrap();
+
+    let args = Args {
+      cmd: Commands::Delete {
+        ids: Vec::new(),
+        filter: Some(String::from("name=buy")),
+        yes: false,
+      },
+      options: GlobalOptions::default(),
+    };
+
+    command_switch(args, &conn).unwrap();
+
+    let tasks = read_tasks(&conn).unwrap();
+    assert!(
+      tasks.len() == 1 && tasks[0].name == "Write report",
+      "Only the tasks matching the filter should be deleted"
+    );
+  }
+
+  #[test]
+  fn test_command_delete_with_missing_id_deletes_nothing() {
+    let conn = setup().unwrap();
+    insert_task(&conn, String::from("Kept")).unwrap();
+
+    let args = Args {
+      cmd: Commands::Delete {
+        ids: vec![String::from("1"), String::from("2")],
+        filter: None,
+        yes: false,
+      },
+      options: GlobalOptions::default(),
+    };
+
+    assert_eq!(command_switch(args, &conn).unwrap_err().exit_code(), 2);
+
+    assert_eq!(read_tasks(&conn).unwrap().len(), 1);
+  }
+
   #[test]
   fn test_command_get_tasks() {
     let conn = setup().unwrap();
@@ -316,28 +1157,32 @@ mod tests {
     let args = Args {
       cmd: Commands::Add {
         name: String::from("Test Task 1"),
-        dry_test: false,
+        remind_at: None,
       },
+      options: GlobalOptions::default(),
     };
     
-    command_switch(args, &conn);
+    command_switch(args, &conn).unwrap();
     
     let args = Args {
       cmd: Commands::Add {
         name: String::from("Test Task 2"),
-        dry_test: false,
+        remind_at: None,
       },
+      options: GlobalOptions::default(),
     };
     
-    command_switch(args, &conn);
+    command_switch(args, &conn).unwrap();
     
     let args = Args {
       cmd: Commands::Get {
-        dry_test: false,
+        completed_since: None,
+        changed_since: None,
       },
+      options: GlobalOptions::default(),
     };
     
-    command_switch(args, &conn);
+    command_switch(args, &conn).unwrap();
     
     let tasks = read_tasks(&conn).unwrap();
 
@@ -346,4 +1191,289 @@ mod tests {
       "The tasks were not listed"
     );
   }
+
+  #[test]
+  fn test_command_user_add_and_remove() {
+    let conn = setup().unwrap();
+
+    let args = Args {
+      cmd: Commands::User {
+        action: UserCommands::Add { name: String::from("alice") },
+      },
+      options: GlobalOptions::default(),
+    };
+
+    command_switch(args, &conn).unwrap();
+
+    let count: u32 = conn.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0)).unwrap();
+    assert!(count == 1, "The user was not added to the database");
+
+    let args = Args {
+      cmd: Commands::User {
+        action: UserCommands::Remove { name: String::from("alice") },
+      },
+      options: GlobalOptions::default(),
+    };
+
+    command_switch(args, &conn).unwrap();
+
+    let count: u32 = conn.query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0)).unwrap();
+    assert!(count == 0, "The user was not removed from the database");
+  }
+
+  #[test]
+  fn test_command_start_and_stop_timer() {
+    let conn = setup().unwrap();
+    let task = insert_task(&conn, String::from("Test Task")).unwrap();
+
+    command_switch(Args { cmd: Commands::Start { id: task.id.to_string() }, options: GlobalOptions::default() }, &conn).unwrap();
+
+    let running: u32 = conn.query_row("SELECT COUNT(*) FROM time_entries WHERE stopped_at IS NULL", [], |row| row.get(0)).unwrap();
+    assert!(running == 1, "The timer was not started");
+
+    command_switch(Args { cmd: Commands::Stop, options: GlobalOptions::default() }, &conn).unwrap();
+
+    let running: u32 = conn.query_row("SELECT COUNT(*) FROM time_entries WHERE stopped_at IS NULL", [], |row| row.get(0)).unwrap();
+    assert!(running == 0, "The timer was not stopped");
+  }
+
+  #[test]
+  fn test_command_backup_writes_file() {
+    let conn = setup().unwrap();
+    insert_task(&conn, String::from("Test Task")).unwrap();
+
+    let dest = std::env::temp_dir().join(format!("rust-to-do-list-{}-command-backup.db", std::process::id()));
+    let _ = std::fs::remove_file(&dest);
+
+    let args = Args {
+      cmd: Commands::Backup {
+        dest: dest.to_string_lossy().to_string(),
+      },
+      options: GlobalOptions::default(),
+    };
+
+    command_switch(args, &conn).unwrap();
+
+    let backup = Connection::open(&dest).unwrap();
+    let tasks = read_tasks(&backup).unwrap();
+    std::fs::remove_file(&dest).unwrap();
+
+    assert!(
+      tasks.len() == 1 && tasks[0].name == "Test Task",
+      "The backup does not contain the task"
+    );
+  }
+
+  #[test]
+  fn test_done_value_accepts_yes_no_and_digits() {
+    for (value, expected) in [("yes", true), ("no", false), ("1", true), ("0", false), ("true", true), ("false", false)] {
+      match Args::try_parse_from(["todo", "update", "--id", "1", "--done", value]).unwrap().cmd {
+        Commands::Update { done, .. } => assert_eq!(done, expected, "--done {value}"),
+        _ => panic!("Expected an update command"),
+      }
+    }
+  }
+
+  #[test]
+  fn test_command_done_and_reopen() {
+    let conn = setup().unwrap();
+    insert_task(&conn, String::from("First")).unwrap();
+    insert_task(&conn, String::from("Second")).unwrap();
+
+    let args = Args::try_parse_from(["todo", "done", "1", "2"]).unwrap();
+    command_switch(args, &conn).unwrap();
+    assert!(read_tasks(&conn).unwrap().iter().all(|task| task.is_done));
+
+    let args = Args::try_parse_from(["todo", "reopen", "2"]).unwrap();
+    command_switch(args, &conn).unwrap();
+    let tasks = read_tasks(&conn).unwrap();
+    assert!(tasks[0].is_done && !tasks[1].is_done);
+  }
+
+  #[test]
+  fn test_command_validation_failures_are_errors() {
+    let conn = setup().unwrap();
+    insert_task(&conn, String::from("Task")).unwrap();
+
+    for line in [
+      vec!["todo", "done", "42"],
+      vec!["todo", "reopen", "3-1"],
+      vec!["todo", "update", "--where", "color=red", "--done", "yes"],
+      vec!["todo", "get", "--completed-since", "yesterday"],
+      vec!["todo", "add", "--name", "Later", "--remind-at", "tomorrow"],
+    ] {
+      let args = Args::try_parse_from(&line).unwrap();
+      assert!(command_switch(args, &conn).is_err(), "{line:?} should fail");
+    }
+    assert_eq!(read_tasks(&conn).unwrap().len(), 1, "A failed add should not insert the task");
+  }
+
+  #[test]
+  fn test_command_errors_have_exit_codes() {
+    let conn = setup().unwrap();
+    insert_task(&conn, String::from("Task")).unwrap();
+    let missing = std::env::temp_dir().join("rust-to-do-list-missing").join("other.db");
+
+    for (line, code) in [
+      (vec!["todo", "done", "42"], 2),
+      (vec!["todo", "stop"], 2),
+      (vec!["todo", "user", "token", "nobody"], 2),
+      (vec!["todo", "done", "3-1"], 3),
+      (vec!["todo", "add", "--name", ""], 3),
+      (vec!["todo", "report", "--since", "last week"], 3),
+      (vec!["todo", "sync", missing.to_str().unwrap()], 4),
+    ] {
+      let args = Args::try_parse_from(&line).unwrap();
+      assert_eq!(command_switch(args, &conn).unwrap_err().exit_code(), code, "{line:?}");
+    }
+    let args = Args::try_parse_from(["todo", "backup", "copy.db"]).unwrap();
+    assert_eq!(command_switch(args, &MemoryStore::new()).unwrap_err().exit_code(), 1);
+  }
+
+  #[test]
+  fn test_command_outcome_holds_affected_tasks() {
+    let conn = setup().unwrap();
+    let run = |line: &str| command_switch(Args::try_parse_from(line.split(' ')).unwrap(), &conn).unwrap();
+
+    let outcome = run("todo add --name Milk");
+    assert!(outcome.messages.is_empty(), "Progress messages are logged, not returned");
+    assert_eq!(outcome.tasks[0].name, "Milk");
+
+    let outcome = run("todo done 1");
+    assert!(outcome.tasks.len() == 1 && outcome.tasks[0].is_done);
+    assert!(outcome.warnings.is_empty());
+
+    let outcome = run("todo delete 1 --json");
+    assert_eq!(outcome.tasks[0].id, 1);
+    assert_eq!(outcome.messages.last().unwrap(), "1 task(s) deleted successfully");
+  }
+
+  #[test]
+  fn test_json_flag_is_global() {
+    for line in ["todo --json get", "todo get --json"] {
+      assert!(Args::try_parse_from(line.split(' ')).unwrap().options.json, "{line}");
+    }
+    assert!(!Args::try_parse_from(["todo", "get"]).unwrap().options.json);
+  }
+
+  #[test]
+  fn test_dry_run_rolls_back() {
+    let conn = setup().unwrap();
+    insert_task(&conn, String::from("Kept")).unwrap();
+
+    for line in ["todo done 1 --dry-run", "todo --dry-run delete 1", "todo add --name Extra --dry-run"] {
+      let outcome = command_switch(Args::try_parse_from(line.split(' ')).unwrap(), &conn).unwrap();
+      assert!(!outcome.tasks.is_empty(), "{line} shows what would change");
+      assert_eq!(outcome.messages.last().unwrap(), "Dry run, nothing was saved");
+    }
+    let tasks = read_tasks(&conn).unwrap();
+    assert!(tasks.len() == 1 && !tasks[0].is_done, "Nothing was saved");
+
+    let args = Args::try_parse_from(["todo", "backup", "copy.db", "--dry-run"]).unwrap();
+    assert_eq!(command_switch(args, &conn).unwrap_err().exit_code(), 1);
+    let args = Args::try_parse_from(["todo", "get", "--dry-run"]).unwrap();
+    assert!(command_switch(args, &MemoryStore::new()).is_err(), "Only SQLite can roll back");
+  }
+
+  #[test]
+  fn test_delete_backup_keeps_the_deleted_task() {
+    let dir = std::env::temp_dir().join(format!("rust-to-do-list-{}-delete-backup", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let conn = crate::db::connect_db_file(dir.join("tasks.db")).unwrap();
+    insert_task(&conn, String::from("Deleted")).unwrap();
+
+    command_switch(Args::try_parse_from(["todo", "delete", "1"]).unwrap(), &conn).unwrap();
+
+    let newest = std::fs::read_dir(dir.join(crate::db::backup::BACKUP_DIR)).unwrap()
+      .map(|entry| entry.unwrap().path())
+      .max()
+      .unwrap();
+    let backup = Connection::open(newest).unwrap();
+    assert_eq!(read_tasks(&backup).unwrap()[0].name, "Deleted", "The backup is taken before the delete");
+    assert!(read_tasks(&conn).unwrap().is_empty());
+    drop((conn, backup));
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  #[test]
+  fn test_dry_run_reports_a_transaction_that_cannot_begin() {
+    let path = std::env::temp_dir().join(format!("rust-to-do-list-{}-busy.db", std::process::id()));
+    let (holder, conn) = (crate::db::open_db(&path).unwrap(), crate::db::open_db(&path).unwrap());
+    crate::db::build_db(&holder).unwrap();
+    conn.busy_timeout(std::time::Duration::ZERO).unwrap();
+    holder.execute_batch("BEGIN IMMEDIATE").unwrap();
+
+    let args = Args::try_parse_from(["todo", "add", "--name", "Extra", "--dry-run"]).unwrap();
+    assert_eq!(command_switch(args, &conn).unwrap_err().exit_code(), 4);
+    let args = Args::try_parse_from(["todo", "get"]).unwrap();
+    assert!(command_switch(args, &conn).is_ok(), "Reads do not wait for the write lock");
+
+    holder.execute_batch("ROLLBACK").unwrap();
+    drop((holder, conn));
+    for suffix in ["", "-wal", "-shm"] {
+      let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+    }
+  }
+
+  #[test]
+  fn test_profile_flags() {
+    let profile = |line: &str| Args::try_parse_from(line.split(' ')).map(|args| args.options.profile_name().to_string());
+
+    assert_eq!(profile("todo get").unwrap(), "default");
+    assert_eq!(profile("todo get -t").unwrap(), "test");
+    assert_eq!(profile("todo --profile work get").unwrap(), "work");
+    assert_eq!(profile("todo get -p sandbox").unwrap(), "sandbox");
+    assert!(profile("todo get -t --profile work").is_err(), "-t is the test profile");
+  }
+
+  #[test]
+  fn test_commands_on_memory_store() {
+    let store = MemoryStore::new();
+
+    for line in ["todo add --name First", "todo add --name Second", "todo done @1", "todo delete 2"] {
+      command_switch(Args::try_parse_from(line.split(' ')).unwrap(), &store).unwrap();
+    }
+
+    let tasks = store.list().unwrap();
+    assert_eq!(tasks.len(), 1);
+    assert!(tasks[0].is_done);
+    let args = Args::try_parse_from(["todo", "backup", "copy.db"]).unwrap();
+    assert!(command_switch(args, &store).is_err(), "Backups need the SQLite storage");
+  }
+
+  #[test]
+  fn test_parallel_commands_on_one_file() {
+    let path = std::env::temp_dir().join(format!("rust-to-do-list-{}-stress.db", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+    let (threads, tasks_per_thread) = (8, 20);
+
+    let handles: Vec<_> = (0..threads).map(|worker| {
+      let path = path.clone();
+      std::thread::spawn(move || {
+        // Each thread is a separate invocation with its own connection
+        let conn = crate::db::open_db(&path).unwrap();
+        // No need to wait for the disk in a throwaway file
+        conn.pragma_update(None, "synchronous", "OFF").unwrap();
+        crate::db::build_db(&conn).unwrap();
+        for index in 0..tasks_per_thread {
+          let name = format!("Task {worker}-{index}");
+          command_switch(Args::try_parse_from(["todo", "add", "--name", &name]).unwrap(), &conn).unwrap();
+          command_switch(Args::try_parse_from(["todo", "done", "@1"]).unwrap(), &conn).unwrap();
+        }
+      })
+    }).collect();
+    for handle in handles {
+      handle.join().unwrap();
+    }
+
+    let conn = crate::db::open_db(&path).unwrap();
+    let tasks = read_tasks(&conn).unwrap();
+    assert_eq!(tasks.len(), threads * tasks_per_thread);
+    assert!(tasks.iter().all(|task| task.is_done), "Every done should find an open task");
+    drop(conn);
+    for suffix in ["", "-wal", "-shm"] {
+      let _ = std::fs::remove_file(format!("{path}{suffix}"));
+    }
+  }
 }
@@ -0,0 +1,243 @@
+use std::io::{self, Write};
+
+use clap::{Command, CommandFactory, ValueEnum, ValueHint};
+use clap_complete::{generate, Shell};
+use rusqlite::{Connection, Result};
+
+use crate::db::task::read_tasks;
+use crate::Args;
+
+/// Name of the hidden subcommand the scripts call to list live task ids.
+pub const COMPLETE_IDS_COMMAND: &str = "__complete-ids";
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum CompletionShell {
+  Bash,
+  Zsh,
+  Fish,
+  Powershell,
+}
+
+impl CompletionShell {
+  fn to_clap_shell(self) -> Shell {
+    match self {
+      CompletionShell::Bash => Shell::Bash,
+      CompletionShell::Zsh => Shell::Zsh,
+      CompletionShell::Fish => Shell::Fish,
+      CompletionShell::Powershell => Shell::PowerShell,
+    }
+  }
+}
+
+/// Arguments taking task ids, as `(subcommand, argument id)`.
+const TASK_ID_ARGS: [(&str, &str); 7] = [
+  ("update", "ids"), ("delete", "ids"), ("done", "ids"), ("reopen", "ids"), ("reminder", "id"), ("start", "id"), ("time", "id"),
+];
+
+/// Subcommands whose positional arguments are task ids.
+fn positional_id_commands() -> Vec<&'static str> {
+  TASK_ID_ARGS.iter().filter(|(command, _)| *command != "update").map(|(command, _)| *command).collect()
+}
+
+/// The `Args` command without the hidden helpers, which the generators
+/// would otherwise list.
+fn completion_command() -> Command {
+  let args = Args::command();
+  Command::new(env!("CARGO_PKG_NAME"))
+    .version(env!("CARGO_PKG_VERSION"))
+    .subcommand_required(true)
+    .args(args.get_arguments().cloned())
+    .subcommands(args.get_subcommands().filter(|sub| !sub.is_hide_set()).cloned())
+}
+
+/// Name of the function clap registers with `complete -F` in a bash script.
+fn bash_function(script: &str) -> Option<&str> {
+  script.lines().find_map(|line| line.trim().strip_prefix("complete -F ")?.split_whitespace().next())
+}
+
+fn bash_ids_snippet(bin: &str, clap_function: &str) -> String {
+  let function = format!("_{}", bin.replace('-', "_"));
+  let commands = positional_id_commands().join("|");
+  format!(r#"
+{function}_ids() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    local prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    local command="" i
+    local -a profile=()
+    for (( i=1; i < COMP_CWORD; i++ )); do
+        case "${{COMP_WORDS[i]}}" in
+            -p|--profile)
+                [[ "${{COMP_WORDS[i+1]}}" == "=" ]] && (( i++ ))
+                profile=(--profile "${{COMP_WORDS[i+1]}}")
+                (( i++ ))
+                ;;
+            -t|--dry-test) profile=(--dry-test) ;;
+            -*) ;;
+            *) [[ -z "$command" ]] && command="${{COMP_WORDS[i]}}" ;;
+        esac
+    done
+    if [[ ( "$command" == update && ( "$prev" == --id || "$prev" == -i ) ) || ( "$command" =~ ^({commands})$ && "$cur" != -* ) ]]; then
+        COMPREPLY=( $(compgen -W "$({bin} "${{profile[@]}}" {COMPLETE_IDS_COMMAND} 2>/dev/null | cut -f1)" -- "$cur") )
+        return 0
+    fi
+    {clap_function} "$@"
+}}
+complete -F {function}_ids -o nosort -o bashdefault -o default {bin}
+"#)
+}
+
+fn zsh_ids_snippet(bin: &str) -> String {
+  let function = format!("_{}", bin.replace('-', "_"));
+  format!(r#"
+{function}_ids() {{
+    local -a ids profile line
+    local i
+    line=(${{(z)LBUFFER}})
+    for (( i = 2; i <= ${{#line}}; i++ )); do
+        case $line[i] in
+            -p|--profile) profile=(--profile $line[i+1]); (( i++ )) ;;
+            --profile=*) profile=($line[i]) ;;
+            -t|--dry-test) profile=(--dry-test) ;;
+        esac
+    done
+    ids=(${{(f)"$({bin} $profile {COMPLETE_IDS_COMMAND} 2>/dev/null | sed 's/\t/:/')"}})
+    _describe 'task id' ids
+}}
+"#)
+}
+
+fn fish_ids_snippet(bin: &str) -> String {
+  let function = format!("__{}_ids", bin.replace('-', "_"));
+  let commands = positional_id_commands().join(" ");
+  format!(r#"
+function {function}
+    set -l profile
+    set -l words (commandline -opc)
+    for i in (seq 2 (count $words))
+        switch $words[$i]
+            case -p --profile
+                test $i -lt (count $words); and set profile --profile $words[(math $i + 1)]
+            case '--profile=*'
+                set profile $words[$i]
+            case -t --dry-test
+                set profile --dry-test
+        end
+    end
+    {bin} $profile {COMPLETE_IDS_COMMAND} 2>/dev/null
+end
+complete -c {bin} -n "__fish_seen_subcommand_from update; and __fish_prev_arg_in -i --id" -f -a "({function})"
+complete -c {bin} -n "__fish_seen_subcommand_from {commands}" -f -a "({function})"
+"#)
+}
+
+/// Generate the completion script for `shell` from the clap `Args`
+/// definition. Bash, zsh and fish also complete task ids from the database
+/// of the profile on the command line; PowerShell only gets the static part.
+pub fn write_completions(shell: CompletionShell, out: &mut impl Write) -> io::Result<()> {
+  let mut command = completion_command();
+  let bin = command.get_name().to_string();
+
+  if shell == CompletionShell::Zsh {
+    // Nothing else completes user names, so the hint marks the task ids
+    for (name, id) in TASK_ID_ARGS {
+      command = command.mut_subcommand(name, |sub| sub.mut_arg(id, |arg| arg.value_hint(ValueHint::Username)));
+    }
+  }
+  let mut buffer = Vec::new();
+  generate(shell.to_clap_shell(), &mut command, &bin, &mut buffer);
+  let script = String::from_utf8_lossy(&buffer).to_string();
+
+  let script = match shell {
+    CompletionShell::Bash => {
+      let clap_function = bash_function(&script)
+        .ok_or_else(|| io::Error::other("The bash script registers no completion function"))?
+        .to_string();
+      script + &bash_ids_snippet(&bin, &clap_function)
+    },
+    CompletionShell::Zsh => {
+      let function = format!("_{}_ids", bin.replace('-', "_"));
+      zsh_ids_snippet(&bin) + &script.replace(":_users'", &format!(":{function}'"))
+    },
+    CompletionShell::Fish => script + &fish_ids_snippet(&bin),
+    CompletionShell::Powershell => script,
+  };
+
+  out.write_all(script.as_bytes())
+}
+
+/// Print one `id<TAB>name` line per live task, for the completion scripts.
+pub fn write_task_ids(conn: &Connection, out: &mut impl Write) -> Result<()> {
+  for task in read_tasks(conn)? {
+    let _ = writeln!(out, "{}\t{}", task.id, task.name);
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::connect_db;
+  use crate::db::task::insert_task;
+
+  fn script(shell: CompletionShell) -> String {
+    let mut out = Vec::new();
+    write_completions(shell, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+  }
+
+  #[test]
+  fn test_completions_list_subcommands() {
+    for shell in [CompletionShell::Bash, CompletionShell::Zsh, CompletionShell::Fish, CompletionShell::Powershell] {
+      let script = script(shell);
+      assert!(script.contains("update"), "{shell:?} script misses the update command");
+      assert!(script.contains("delete"), "{shell:?} script misses the delete command");
+      assert!(!script.contains("'__complete-ids'"), "{shell:?} script exposes the hidden helper");
+    }
+  }
+
+  #[test]
+  fn test_completions_include_global_flags() {
+    for shell in [CompletionShell::Bash, CompletionShell::Zsh, CompletionShell::Fish, CompletionShell::Powershell] {
+      let script = script(shell);
+      // Fish writes long flags as `-l dry-run`
+      for flag in ["dry-run", "json", "log-file", "quiet"] {
+        assert!(script.contains(flag), "{shell:?} script misses {flag}");
+      }
+    }
+  }
+
+  #[test]
+  fn test_dynamic_scripts_call_the_ids_helper() {
+    assert!(script(CompletionShell::Bash).contains(COMPLETE_IDS_COMMAND));
+    assert!(script(CompletionShell::Fish).contains(COMPLETE_IDS_COMMAND));
+
+    let zsh = script(CompletionShell::Zsh);
+    assert!(zsh.contains(COMPLETE_IDS_COMMAND));
+    assert!(zsh.contains(":IDS:_rust_to_do_list_ids'"));
+    assert!(zsh.contains(":ids -- Ids, @indexes or uuids of the tasks, or ranges such as 3-9:_rust_to_do_list_ids'"));
+    assert!(zsh.contains(":id -- Id, @index or uuid of the task:_rust_to_do_list_ids'"));
+    assert!(!zsh.contains("_users"), "Only task ids use the marker hint");
+  }
+
+  #[test]
+  fn test_bash_falls_back_to_a_defined_function() {
+    let bash = script(CompletionShell::Bash);
+    let ids = bash.split("_rust_to_do_list_ids() {").nth(1).unwrap();
+    let fallback = ids.lines().rev().find(|line| line.trim().ends_with("\"$@\"")).unwrap().split_whitespace().next().unwrap();
+
+    assert!(bash.contains(&format!("{fallback}() {{")), "{fallback} is not defined");
+    assert!(bash.contains("complete -F _rust_to_do_list_ids"));
+  }
+
+  #[test]
+  fn test_write_task_ids() {
+    let conn = connect_db(Some(true), None).unwrap();
+    insert_task(&conn, "Buy milk".to_string()).unwrap();
+    insert_task(&conn, "Write report".to_string()).unwrap();
+
+    let mut out = Vec::new();
+    write_task_ids(&conn, &mut out).unwrap();
+
+    assert_eq!(String::from_utf8(out).unwrap(), "1\tBuy milk\n2\tWrite report\n");
+  }
+}
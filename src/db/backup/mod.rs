@@ -0,0 +1,202 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use rusqlite::backup::Progress;
+use rusqlite::{ffi, Connection, DatabaseName, Error, OpenFlags, Result};
+
+use super::{build_db, SCHEMA_VERSION};
+
+/// Number of automatic backups kept per database file.
+pub const MAX_ROTATING_BACKUPS: usize = 5;
+
+/// Directory, next to the database file, where rotating backups are written.
+pub const BACKUP_DIR: &str = "backups";
+
+/// Copy the whole database into `dest` using SQLite's online backup API,
+/// so it is safe to run while the database is in use.
+pub fn backup_db(conn: &Connection, dest: &str) -> Result<()> {
+  conn.backup(DatabaseName::Main, dest, None)
+}
+
+/// Check that `src` is a usable backup: it must pass `PRAGMA integrity_check`
+/// and must not come from a newer schema than the one this binary knows.
+pub fn verify_backup(src: &str) -> Result<i32> {
+  if !Path::new(src).is_file() {
+    return Err(Error::InvalidPath(PathBuf::from(src)));
+  }
+  let backup = Connection::open_with_flags(src, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+
+  let integrity: String = backup.query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+  if integrity != "ok" {
+    return Err(Error::SqliteFailure(
+      ffi::Error::new(ffi::SQLITE_CORRUPT),
+      Some(format!("Backup failed integrity check: {integrity}")),
+    ));
+  }
+
+  let version: i32 = backup.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+  if version > SCHEMA_VERSION {
+    return Err(Error::SqliteFailure(
+      ffi::Error::new(ffi::SQLITE_MISMATCH),
+      Some(format!("Backup schema version {version} is newer than supported version {SCHEMA_VERSION}")),
+    ));
+  }
+
+  Ok(version)
+}
+
+/// Replace the live database behind `conn` with the contents of `src`.
+/// The backup is verified first and migrated to the current schema afterwards.
+pub fn restore_db(conn: &Connection, src: &str) -> Result<()> {
+  verify_backup(src)?;
+
+  let live_path = match conn.path() {
+    Some(path) if !path.is_empty() => path.to_string(),
+    _ => return Err(Error::InvalidPath(PathBuf::from(":memory:"))),
+  };
+
+  let mut live = Connection::open(live_path)?;
+  live.restore(DatabaseName::Main, src, None::<fn(Progress)>)?;
+
+  build_db(conn)
+}
+
+/// Take an automatic backup of the database behind `conn` into the
+/// `backups` directory next to it, keeping only the newest
+/// `MAX_ROTATING_BACKUPS` files. In-memory databases are skipped.
+pub fn rotate_backups(conn: &Connection) -> Result<Option<PathBuf>> {
+  let db_path = match conn.path() {
+    Some(path) if !path.is_empty() => PathBuf::from(path),
+    _ => return Ok(None),
+  };
+
+  let stem = db_path.file_stem().and_then(|s| s.to_str()).unwrap_or("tasks").to_string();
+  let dir = db_path.parent().unwrap_or(Path::new(".")).join(BACKUP_DIR);
+  fs::create_dir_all(&dir).map_err(|_| Error::InvalidPath(dir.clone()))?;
+
+  let file_name = format!("{stem}-{}.db", Local::now().format("%Y%m%d%H%M%S%3f"));
+  let dest = dir.join(file_name);
+  backup_db(conn, &dest.to_string_lossy())?;
+
+  // Names embed a sortable timestamp, so lexical order is chronological order
+  let prefix = format!("{stem}-");
+  let mut existing: Vec<PathBuf> = fs::read_dir(&dir)
+    .map_err(|_| Error::InvalidPath(dir.clone()))?
+    .filter_map(|entry| entry.ok().map(|e| e.path()))
+    .filter(|path| {
+      path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.starts_with(&prefix) && name.ends_with(".db"))
+        .unwrap_or(false)
+    })
+    .collect();
+  existing.sort();
+
+  while existing.len() > MAX_ROTATING_BACKUPS {
+    let oldest = existing.remove(0);
+    let _ = fs::remove_file(oldest);
+  }
+
+  Ok(Some(dest))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::connect_db;
+  use super::super::task::{insert_task, read_tasks};
+  use std::env;
+
+  fn temp_path(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("rust-to-do-list-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+  }
+
+  fn open_file_db(path: &Path) -> Connection {
+    let conn = Connection::open(path).unwrap();
+    build_db(&conn).unwrap();
+    conn
+  }
+
+  #[test]
+  fn test_backup_and_restore_round_trip() {
+    let dir = temp_path("round-trip");
+    let conn = connect_db(Some(true), None).unwrap();
+    insert_task(&conn, "Backed up task".to_string()).unwrap();
+
+    let dest = dir.join("backup.db");
+    backup_db(&conn, dest.to_str().unwrap()).unwrap();
+
+    let live = open_file_db(&dir.join("live.db"));
+    insert_task(&live, "Task that will be replaced".to_string()).unwrap();
+
+    restore_db(&live, dest.to_str().unwrap()).unwrap();
+
+    let tasks = read_tasks(&live).unwrap();
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0].name, "Backed up task");
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_verify_backup_rejects_corrupted_file() {
+    let dir = temp_path("corrupted");
+    let src = dir.join("garbage.db");
+    fs::write(&src, "this is not a sqlite database").unwrap();
+
+    assert!(verify_backup(src.to_str().unwrap()).is_err(), "Expected corrupted backup to be rejected");
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_verify_backup_rejects_newer_schema() {
+    let dir = temp_path("newer-schema");
+    let src = dir.join("future.db");
+    let conn = open_file_db(&src);
+    conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1).unwrap();
+    drop(conn);
+
+    assert!(verify_backup(src.to_str().unwrap()).is_err(), "Expected newer schema backup to be rejected");
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_restore_fails_for_missing_file() {
+    let dir = temp_path("missing");
+    let live = open_file_db(&dir.join("live.db"));
+    insert_task(&live, "Live task".to_string()).unwrap();
+
+    assert!(restore_db(&live, dir.join("nope.db").to_str().unwrap()).is_err());
+    assert_eq!(read_tasks(&live).unwrap().len(), 1, "Live database should be untouched");
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_rotate_backups_keeps_newest_files() {
+    let dir = temp_path("rotate");
+    let live = open_file_db(&dir.join("live.db"));
+
+    for _ in 0..MAX_ROTATING_BACKUPS + 2 {
+      rotate_backups(&live).unwrap();
+      std::thread::sleep(std::time::Duration::from_millis(2));
+    }
+
+    let count = fs::read_dir(dir.join(BACKUP_DIR)).unwrap().count();
+    assert_eq!(count, MAX_ROTATING_BACKUPS);
+
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_rotate_backups_skips_in_memory_database() {
+    let conn = connect_db(Some(true), None).unwrap();
+    assert!(rotate_backups(&conn).unwrap().is_none());
+  }
+}
@@ -0,0 +1,237 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use super::selection::TaskFilter;
+use super::store::{MemoryStore, StoreResult, TaskStore};
+use super::task::Task;
+
+/// Environment variable choosing the storage of the command line, `sqlite`
+/// (the default) or `json`.
+pub const STORAGE_VAR: &str = "TODO_STORAGE";
+
+/// File of the JSON storage standing in for the database file `database`,
+/// e.g. `tasks.json` for `tasks.db`.
+pub fn json_path(database: &Path) -> PathBuf {
+  database.with_extension("json")
+}
+
+/// Tasks kept in a JSON file, for machines where SQLite files cannot be
+/// used. Every operation locks a `.lock` file next to it, so concurrent
+/// invocations take turns, and changes are written to a temporary file
+/// renamed over the old one, so a crash never leaves half a file.
+pub struct JsonStore {
+  path: PathBuf,
+  /// The exclusive lock kept by `open_exclusive` until the store is dropped.
+  held: Option<File>,
+}
+
+impl JsonStore {
+  /// Open the storage at `path`, creating an empty one if needed.
+  pub fn open(path: impl AsRef<Path>) -> io::Result<JsonStore> {
+    let store = JsonStore { path: path.as_ref().to_path_buf(), held: None };
+    let _lock = store.lock(true)?;
+    if !store.path.exists() {
+      store.save(&[])?;
+    }
+
+    Ok(store)
+  }
+
+  /// Open the storage at `path` and keep it locked until the store is
+  /// dropped, so a whole command, from resolving ids to saving, sees no
+  /// change from other invocations in between.
+  pub fn open_exclusive(path: impl AsRef<Path>) -> io::Result<JsonStore> {
+    let mut store = JsonStore::open(path)?;
+    store.held = Some(store.lock(true)?);
+
+    Ok(store)
+  }
+
+  fn sibling(&self, extension: &str) -> PathBuf {
+    let mut name = self.path.file_name().unwrap_or_default().to_os_string();
+    name.push(extension);
+    self.path.with_file_name(name)
+  }
+
+  /// Hold the lock file until the returned handle is dropped, shared
+  /// between readers and exclusive for writers.
+  fn lock(&self, exclusive: bool) -> io::Result<File> {
+    let file = OpenOptions::new().create(true).truncate(false).write(true).open(self.sibling(".lock"))?;
+    if exclusive { file.lock()? } else { file.lock_shared()? }
+
+    Ok(file)
+  }
+
+  fn load(&self) -> io::Result<Vec<Task>> {
+    let text = fs::read_to_string(&self.path)?;
+    serde_json::from_str(&text).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+  }
+
+  fn save(&self, tasks: &[Task]) -> io::Result<()> {
+    let temporary = self.sibling(".tmp");
+    let mut file = File::create(&temporary)?;
+    serde_json::to_writer_pretty(&mut file, tasks).map_err(io::Error::other)?;
+    file.write_all(b"\n")?;
+    file.sync_all()?;
+    fs::rename(temporary, &self.path)
+  }
+
+  /// Run `operation` on the tasks of the file, saving them afterwards when
+  /// `write` is set and the operation succeeded.
+  fn with_tasks<T>(&self, write: bool, operation: impl FnOnce(&MemoryStore) -> StoreResult<T>) -> StoreResult<T> {
+    let _lock = match self.held {
+      Some(_) => None,
+      None => Some(self.lock(write)?),
+    };
+    let tasks = MemoryStore::from_tasks(self.load()?);
+
+    let result = operation(&tasks)?;
+    if write {
+      self.save(&tasks.into_tasks())?;
+    }
+
+    Ok(result)
+  }
+}
+
+impl TaskStore for JsonStore {
+  fn insert(&self, name: String) -> StoreResult<Task> {
+    self.with_tasks(true, |tasks| tasks.insert(name))
+  }
+
+  fn list(&self) -> StoreResult<Vec<Task>> {
+    self.with_tasks(false, |tasks| tasks.list())
+  }
+
+  fn get(&self, id: u32) -> StoreResult<Task> {
+    self.with_tasks(false, |tasks| tasks.get(id))
+  }
+
+  fn update(&self, ids: &[u32], is_done: bool, completed_by: Option<&str>) -> StoreResult<Vec<Task>> {
+    self.with_tasks(true, |tasks| tasks.update(ids, is_done, completed_by))
+  }
+
+  fn delete(&self, ids: &[u32]) -> StoreResult<Vec<Task>> {
+    self.with_tasks(true, |tasks| tasks.delete(ids))
+  }
+
+  fn restore(&self, id: u32) -> StoreResult<Task> {
+    self.with_tasks(true, |tasks| tasks.restore(id))
+  }
+
+  fn all(&self) -> StoreResult<Vec<Task>> {
+    self.with_tasks(false, |tasks| tasks.all())
+  }
+
+  fn changed_since(&self, since: &str) -> StoreResult<Vec<Task>> {
+    self.with_tasks(false, |tasks| tasks.changed_since(since))
+  }
+
+  fn import(&self, imported: &[Task]) -> StoreResult<()> {
+    self.with_tasks(true, |tasks| tasks.import(imported))
+  }
+
+  fn resolve(&self, values: &[String]) -> StoreResult<Vec<u32>> {
+    self.with_tasks(false, |tasks| tasks.resolve(values))
+  }
+
+  fn select(&self, ids: &[u32], filter: Option<&TaskFilter>) -> StoreResult<Vec<u32>> {
+    self.with_tasks(false, |tasks| tasks.select(ids, filter))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::connect_db;
+  use super::super::store::migrate_tasks;
+  use std::sync::Arc;
+  use std::thread;
+
+  /// A JSON storage of its own for each test, removed when the test ends.
+  struct TempStore {
+    store: JsonStore,
+  }
+
+  impl Drop for TempStore {
+    fn drop(&mut self) {
+      for path in [self.store.path.clone(), self.store.sibling(".lock"), self.store.sibling(".tmp")] {
+        let _ = fs::remove_file(path);
+      }
+    }
+  }
+
+  fn setup(name: &str) -> TempStore {
+    // Set up a fresh file for each test
+    let path = std::env::temp_dir().join(format!("rust-to-do-list-{}-json-{name}.json", std::process::id()));
+    let _ = fs::remove_file(&path);
+    TempStore { store: JsonStore::open(path).unwrap() }
+  }
+
+  #[test]
+  fn test_changes_are_saved_to_the_file() {
+    let temp = setup("saved");
+    let task = temp.store.insert("Saved".to_string()).unwrap();
+    temp.store.update(&[task.id], true, None).unwrap();
+
+    let reopened = JsonStore::open(&temp.store.path).unwrap();
+    assert!(reopened.get(task.id).unwrap().is_done);
+    assert!(!temp.store.sibling(".tmp").exists(), "The temporary file is renamed over the storage");
+
+    // A failed change leaves the file as it was
+    assert!(reopened.delete(&[task.id, 999]).is_err());
+    assert_eq!(reopened.list().unwrap().len(), 1);
+  }
+
+  #[test]
+  fn test_concurrent_inserts_are_not_lost() {
+    let temp = Arc::new(setup("concurrent"));
+
+    let handles: Vec<_> = (0..4).map(|worker| {
+      let temp = Arc::clone(&temp);
+      thread::spawn(move || {
+        // Each thread opens the file on its own, like separate invocations
+        let store = JsonStore::open(&temp.store.path).unwrap();
+        for index in 0..10 {
+          store.insert(format!("Task {worker}-{index}")).unwrap();
+        }
+      })
+    }).collect();
+    for handle in handles {
+      handle.join().unwrap();
+    }
+
+    let tasks = temp.store.list().unwrap();
+    assert_eq!(tasks.len(), 40);
+    let mut ids: Vec<u32> = tasks.iter().map(|task| task.id).collect();
+    ids.dedup();
+    assert_eq!(ids.len(), 40, "Ids stay unique");
+  }
+
+  #[test]
+  fn test_exclusive_store_keeps_others_waiting() {
+    let temp = setup("exclusive");
+    let store = JsonStore::open_exclusive(&temp.store.path).unwrap();
+    store.insert("First".to_string()).unwrap();
+
+    let path = temp.store.path.clone();
+    let other = thread::spawn(move || JsonStore::open(path).unwrap().insert("Second".to_string()).unwrap());
+    thread::sleep(std::time::Duration::from_millis(200));
+    assert_eq!(store.list().unwrap().len(), 1, "The other invocation waits for the lock");
+
+    drop(store);
+    assert_eq!(other.join().unwrap().id, 2);
+    assert_eq!(temp.store.list().unwrap().len(), 2);
+  }
+
+  #[test]
+  fn test_migrate_from_sqlite() {
+    let temp = setup("migrate");
+    let conn = connect_db(Some(true), None).unwrap();
+    conn.insert("Migrated".to_string()).unwrap();
+
+    assert_eq!(migrate_tasks(&conn, &temp.store).unwrap(), 1);
+    assert_eq!(temp.store.list().unwrap()[0].name, "Migrated");
+  }
+}
@@ -1,7 +1,33 @@
+pub mod backup;
+#[cfg(feature = "json-store")]
+pub mod json_store;
+pub mod reminder;
+pub mod selection;
+pub mod stats;
+pub mod store;
+pub mod sync;
 pub mod task;
+pub mod time_entry;
+pub mod user;
+
+use std::path::Path;
+use std::time::Duration;
 
 pub use rusqlite::{Connection, Result};
+use rusqlite::{Transaction, TransactionBehavior};
+use crate::logging::{log_sql, SQL_TARGET};
+use reminder::migrate_remind_at_to_utc;
+use sync::build_sync_db_table;
 use task::build_tasks_db_table;
+use time_entry::build_time_entries_db_table;
+use user::build_users_db_table;
+
+/// Version of the database schema, stored in `PRAGMA user_version`.
+pub const SCHEMA_VERSION: i32 = 10;
+
+/// How long a connection waits for another one to release the database
+/// before failing with `database is locked`.
+pub const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub fn connect_db(is_test: Option<bool>, is_dry_test: Option<bool>) -> Result<Connection> {
   let db_path = match is_test {
@@ -11,20 +37,104 @@ pub fn connect_db(is_test: Option<bool>, is_dry_test: Option<bool>) -> Result<Co
       _ => "tasks.db"
     }
   };
-  let conn = Connection::open(db_path)?;
 
-  build_db(&conn).unwrap();
+  connect_db_file(db_path)
+}
 
-  return Ok(conn);
+/// Open the database file at `path` and migrate it to the current schema.
+pub fn connect_db_file(path: impl AsRef<Path>) -> Result<Connection> {
+  let conn = open_db(path)?;
+  build_db(&conn)?;
+
+  Ok(conn)
+}
+
+/// Open the database file at `path`, set up so several processes can use
+/// it at once: readers never block the writer thanks to WAL journaling,
+/// and writers wait up to `BUSY_TIMEOUT` for each other.
+pub fn open_db(path: impl AsRef<Path>) -> Result<Connection> {
+  log::debug!("Opening database {}", path.as_ref().display());
+  let mut conn = Connection::open(path)?;
+  configure_connection(&conn)?;
+  trace_sql(&mut conn);
+
+  Ok(conn)
+}
+
+/// Log every statement run on `conn` with its duration when tracing, with `-vv`.
+pub fn trace_sql(conn: &mut Connection) {
+  if log::log_enabled!(target: SQL_TARGET, log::Level::Trace) {
+    conn.profile(Some(log_sql));
+  }
+}
+
+/// Apply the busy timeout and, for files, WAL journaling to `conn`.
+pub fn configure_connection(conn: &Connection) -> Result<()> {
+  conn.busy_timeout(BUSY_TIMEOUT)?;
+  if !conn.path().unwrap_or_default().is_empty() {
+    conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+  }
+
+  Ok(())
 }
 
 pub fn build_db(conn: &Connection) -> Result<()> {
-  build_tasks_db_table(conn).unwrap();
+  // Concurrent first runs must not migrate the schema twice
+  with_transaction(conn, |conn| {
+    let version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    build_users_db_table(conn)?;
+    build_tasks_db_table(conn).unwrap();
+    build_time_entries_db_table(conn)?;
+    build_sync_db_table(conn)?;
+    migrate_remind_at_to_utc(conn, version)?;
+    conn.pragma_update(None, "user_version", SCHEMA_VERSION)
+  })
+}
+
+/// Run `operation` in a transaction, committed when it succeeds and rolled
+/// back otherwise. The transaction takes the write lock up front, so two
+/// connections never both read and then fail to upgrade. Inside a
+/// transaction already open on `conn` it runs in a savepoint instead.
+pub fn with_transaction<T>(conn: &Connection, operation: impl FnOnce(&Connection) -> Result<T>) -> Result<T> {
+  if !conn.is_autocommit() {
+    conn.execute_batch("SAVEPOINT nested")?;
+    return match operation(conn) {
+      Ok(value) => {
+        conn.execute_batch("RELEASE nested")?;
+        Ok(value)
+      },
+      Err(err) => {
+        conn.execute_batch("ROLLBACK TO nested; RELEASE nested")?;
+        Err(err)
+      },
+    };
+  }
+
+  let transaction = Transaction::new_unchecked(conn, TransactionBehavior::Immediate)?;
+  let value = operation(&transaction)?;
+  transaction.commit()?;
+
+  Ok(value)
+}
+
+/// Add `column` to `table` when an older database does not have it yet.
+pub fn add_column_if_missing(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+  let mut statement = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+  let columns = statement.query_map([], |row| row.get::<_, String>(1))?;
+
+  for existing in columns {
+    if existing? == column {
+      return Ok(());
+    }
+  }
+
+  conn.execute(&format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"), [])?;
 
   Ok(())
 }
 
 #[cfg(test)]
+#[allow(clippy::needless_return, clippy::assertions_on_constants)]
 mod tests {
   use super::*;
 
@@ -80,4 +190,79 @@ mod tests {
       }
     }
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn test_with_transaction_rolls_back_and_nests() {
+    let conn = setup().unwrap();
+    let count = |conn: &Connection| conn.query_row("SELECT COUNT(*) FROM tasks", [], |row| row.get::<_, u32>(0)).unwrap();
+
+    let failed: Result<()> = with_transaction(&conn, |conn| {
+      conn.execute("INSERT INTO tasks (name, is_done) VALUES ('Rolled back', 0)", [])?;
+      Err(rusqlite::Error::QueryReturnedNoRows)
+    });
+    assert!(failed.is_err());
+    assert_eq!(count(&conn), 0);
+
+    with_transaction(&conn, |conn| {
+      conn.execute("INSERT INTO tasks (name, is_done) VALUES ('Kept', 0)", [])?;
+      // A failing inner operation only undoes its own changes
+      let inner: Result<()> = with_transaction(conn, |conn| {
+        conn.execute("INSERT INTO tasks (name, is_done) VALUES ('Inner', 0)", [])?;
+        Err(rusqlite::Error::QueryReturnedNoRows)
+      });
+      assert!(inner.is_err());
+      Ok(())
+    }).unwrap();
+    assert_eq!(count(&conn), 1);
+    assert!(conn.is_autocommit());
+  }
+
+  #[test]
+  fn test_file_database_uses_wal() {
+    let path = std::env::temp_dir().join(format!("rust-to-do-list-{}-wal.db", std::process::id()));
+    let conn = open_db(path.to_str().unwrap()).unwrap();
+
+    let mode: String = conn.query_row("PRAGMA journal_mode", [], |row| row.get(0)).unwrap();
+    assert_eq!(mode, "wal");
+    drop(conn);
+    for suffix in ["", "-wal", "-shm"] {
+      let _ = std::fs::remove_file(format!("{}{suffix}", path.display()));
+    }
+  }
+
+  #[test]
+  fn test_add_column_if_missing_is_idempotent() {
+    let conn = setup().unwrap();
+
+    add_column_if_missing(&conn, "tasks", "extra", "TEXT NULL").unwrap();
+    add_column_if_missing(&conn, "tasks", "extra", "TEXT NULL").unwrap();
+
+    conn.execute("UPDATE tasks SET extra = 'value'", []).unwrap();
+  }
+
+  #[test]
+  fn test_build_db_migrates_old_tasks_table() {
+    let conn = Connection::open_in_memory().unwrap();
+    conn.execute(
+      "CREATE TABLE tasks (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+        deleted_at DATETIME NULL,
+        name TEXT NOT NULL,
+        is_done BOOLEAN NOT NULL
+      )",
+      [],
+    ).unwrap();
+    conn.execute("INSERT INTO tasks (name, is_done) VALUES ('Old one', 0), ('Old two', 1)", []).unwrap();
+
+    build_db(&conn).unwrap();
+
+    conn.execute("UPDATE tasks SET owner_id = NULL", []).unwrap();
+    let distinct_uuids: u32 = conn.query_row("SELECT COUNT(DISTINCT uuid) FROM tasks", [], |row| row.get(0)).unwrap();
+    assert_eq!(distinct_uuids, 2, "Existing tasks should get their own uuid");
+    let missing_updated_at: u32 = conn.query_row("SELECT COUNT(*) FROM tasks WHERE updated_at IS NULL", [], |row| row.get(0)).unwrap();
+    assert_eq!(missing_updated_at, 0, "Existing tasks should get an updated_at");
+    let version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+    assert_eq!(version, SCHEMA_VERSION);
+  }
+}
@@ -0,0 +1,243 @@
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use rusqlite::{Connection, Error, Result};
+
+use super::task::{task_from_row, Task, TASK_COLUMNS, UPDATED_AT_NOW};
+
+/// Format reminder times are stored in, in UTC like `created_at`.
+pub const REMIND_AT_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// First schema version storing reminder times in UTC.
+pub const REMIND_AT_UTC_VERSION: i32 = 10;
+
+pub struct Reminder {
+  pub task: Task,
+  pub remind_at: String,
+}
+
+/// Parse a user supplied reminder time, in local time, into the stored
+/// UTC format. Accepts `YYYY-MM-DD HH:MM[:SS]` (or with a `T`) and plain
+/// `YYYY-MM-DD`.
+pub fn parse_remind_at(value: &str) -> Result<String> {
+  parse_remind_at_in(value, &Local)
+}
+
+/// Same as `parse_remind_at`, reading `value` in the time zone `zone`.
+pub fn parse_remind_at_in<Tz: TimeZone>(value: &str, zone: &Tz) -> Result<String> {
+  let value = value.trim();
+  let formats = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%dT%H:%M"];
+
+  let datetime = formats.iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+    .or_else(|| NaiveDate::parse_from_str(value, "%Y-%m-%d").ok().and_then(|date| date.and_hms_opt(0, 0, 0)))
+    .ok_or_else(|| Error::InvalidParameterName(format!("Invalid reminder time '{value}', expected YYYY-MM-DD HH:MM")))?;
+  // A time skipped by a daylight saving change never happens locally
+  let datetime = zone.from_local_datetime(&datetime).earliest()
+    .ok_or_else(|| Error::InvalidParameterName(format!("Reminder time '{value}' does not exist in the local time zone")))?;
+
+  Ok(datetime.with_timezone(&Utc).format(REMIND_AT_FORMAT).to_string())
+}
+
+/// Show a stored reminder time in local time.
+pub fn local_remind_at(remind_at: &str) -> String {
+  match NaiveDateTime::parse_from_str(remind_at, REMIND_AT_FORMAT) {
+    Ok(datetime) => Utc.from_utc_datetime(&datetime).with_timezone(&Local).format(REMIND_AT_FORMAT).to_string(),
+    Err(_) => remind_at.to_string(),
+  }
+}
+
+/// Convert the reminder times of databases from before they were stored in
+/// UTC, when `version` is the schema version the database was at.
+pub fn migrate_remind_at_to_utc(conn: &Connection, version: i32) -> Result<()> {
+  if version < REMIND_AT_UTC_VERSION {
+    // SQLite reads the stored time as local time and converts it to UTC
+    conn.execute("UPDATE tasks SET remind_at = datetime(remind_at, 'utc') WHERE remind_at IS NOT NULL", [])?;
+  }
+
+  Ok(())
+}
+
+/// Schedule a reminder for a live task, re-arming it if it already fired.
+pub fn set_task_reminder(conn: &Connection, id: u32, remind_at: &str) -> Result<()> {
+  let remind_at = parse_remind_at(remind_at)?;
+  let sql = format!("UPDATE tasks SET remind_at = ?, reminded_at = NULL,
+      updated_at = {UPDATED_AT_NOW}, remind_updated_at = {UPDATED_AT_NOW}
+    WHERE id = ? AND deleted_at IS NULL");
+  let updated = conn.execute(&sql, [&remind_at as &dyn rusqlite::ToSql, &id])?;
+  if updated == 0 {
+    return Err(Error::QueryReturnedNoRows);
+  }
+
+  Ok(())
+}
+
+/// Remove the reminder of a live task.
+pub fn clear_task_reminder(conn: &Connection, id: u32) -> Result<()> {
+  let sql = format!("UPDATE tasks SET remind_at = NULL, reminded_at = NULL,
+      updated_at = {UPDATED_AT_NOW}, remind_updated_at = {UPDATED_AT_NOW}
+    WHERE id = ? AND deleted_at IS NULL");
+  let updated = conn.execute(&sql, [&id])?;
+  if updated == 0 {
+    return Err(Error::QueryReturnedNoRows);
+  }
+
+  Ok(())
+}
+
+/// Open, live tasks whose reminder time is at or before `now`, in UTC, and
+/// that were not reminded yet.
+pub fn read_due_reminders(conn: &Connection, now: &str) -> Result<Vec<Reminder>> {
+  let sql = format!("SELECT {TASK_COLUMNS}, remind_at FROM tasks
+    WHERE remind_at IS NOT NULL AND remind_at <= ? AND reminded_at IS NULL
+      AND deleted_at IS NULL AND is_done = 0
+    ORDER BY remind_at");
+  let mut statement = conn.prepare(&sql)?;
+
+  let reminders_iter = statement.query_map([now], |row| {
+    Ok(Reminder {
+      task: task_from_row(row)?,
+      remind_at: row.get("remind_at")?,
+    })
+  })?;
+
+  let mut reminders = Vec::new();
+  for reminder in reminders_iter {
+    reminders.push(reminder?);
+  }
+
+  Ok(reminders)
+}
+
+/// Record that the reminder of task `id` fired. Returns `false` when another
+/// process already claimed it, so each reminder is delivered only once. The
+/// claim moves the reminder clock so `sync` carries it to the other replicas.
+pub fn claim_reminder(conn: &Connection, id: u32) -> Result<bool> {
+  let sql = format!("UPDATE tasks SET reminded_at = CURRENT_TIMESTAMP,
+      updated_at = {UPDATED_AT_NOW}, remind_updated_at = {UPDATED_AT_NOW}
+    WHERE id = ? AND reminded_at IS NULL");
+  let updated = conn.execute(&sql, [&id])?;
+
+  Ok(updated == 1)
+}
+
+/// Give back the claim on the reminder of task `id` after it could not be
+/// delivered, so the next poll tries again.
+pub fn release_reminder(conn: &Connection, id: u32) -> Result<()> {
+  let sql = format!("UPDATE tasks SET reminded_at = NULL,
+      updated_at = {UPDATED_AT_NOW}, remind_updated_at = {UPDATED_AT_NOW}
+    WHERE id = ? AND reminded_at IS NOT NULL");
+  conn.execute(&sql, [&id])?;
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::connect_db;
+  use super::super::sync::sync_databases;
+  use super::super::task::{insert_task, update_task_status};
+
+  fn setup() -> Result<Connection>{
+    // Set up test environment
+    connect_db(Some(true), None)
+  }
+
+  /// The stored form of the local time `value`.
+  fn utc(value: &str) -> String {
+    parse_remind_at(value).unwrap()
+  }
+
+  #[test]
+  fn test_parse_remind_at_formats() {
+    assert_eq!(parse_remind_at_in("2030-01-02 03:04", &Utc).unwrap(), "2030-01-02 03:04:00");
+    assert_eq!(parse_remind_at_in("2030-01-02T03:04:05", &Utc).unwrap(), "2030-01-02 03:04:05");
+    assert_eq!(parse_remind_at_in("2030-01-02", &Utc).unwrap(), "2030-01-02 00:00:00");
+    assert!(parse_remind_at("tomorrow").is_err());
+  }
+
+  #[test]
+  fn test_remind_at_is_stored_in_utc() {
+    let paris = chrono::FixedOffset::east_opt(3600).unwrap();
+    assert_eq!(parse_remind_at_in("2030-01-02 00:30", &paris).unwrap(), "2030-01-01 23:30:00");
+    assert_eq!(local_remind_at(&utc("2030-01-02 03:04")), "2030-01-02 03:04:00");
+
+    let conn = setup().unwrap();
+    let task = insert_task(&conn, "Task".to_string()).unwrap();
+    conn.execute("UPDATE tasks SET remind_at = '2030-01-02 03:04:00' WHERE id = ?", [task.id]).unwrap();
+    migrate_remind_at_to_utc(&conn, REMIND_AT_UTC_VERSION - 1).unwrap();
+    migrate_remind_at_to_utc(&conn, REMIND_AT_UTC_VERSION).unwrap();
+    let stored: String = conn.query_row("SELECT remind_at FROM tasks", [], |row| row.get(0)).unwrap();
+    assert_eq!(stored, utc("2030-01-02 03:04"));
+  }
+
+  #[test]
+  fn test_due_reminders_respect_time() {
+    let conn = setup().unwrap();
+    let early = insert_task(&conn, "Early".to_string()).unwrap();
+    let late = insert_task(&conn, "Late".to_string()).unwrap();
+    insert_task(&conn, "No reminder".to_string()).unwrap();
+    set_task_reminder(&conn, early.id, "2030-01-01 08:00").unwrap();
+    set_task_reminder(&conn, late.id, "2030-01-01 18:00").unwrap();
+
+    let due = read_due_reminders(&conn, &utc("2030-01-01 12:00")).unwrap();
+
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].task.id, early.id);
+    assert_eq!(due[0].remind_at, utc("2030-01-01 08:00"));
+  }
+
+  #[test]
+  fn test_claim_reminder_only_once() {
+    let conn = setup().unwrap();
+    let task = insert_task(&conn, "Task".to_string()).unwrap();
+    set_task_reminder(&conn, task.id, "2030-01-01 08:00").unwrap();
+
+    assert!(claim_reminder(&conn, task.id).unwrap());
+    assert!(!claim_reminder(&conn, task.id).unwrap());
+    assert!(read_due_reminders(&conn, &utc("2031-01-01 00:00")).unwrap().is_empty());
+
+    // A released claim can be taken again
+    release_reminder(&conn, task.id).unwrap();
+    assert!(claim_reminder(&conn, task.id).unwrap());
+
+    // Scheduling again re-arms the reminder
+    set_task_reminder(&conn, task.id, "2030-02-01 08:00").unwrap();
+    assert_eq!(read_due_reminders(&conn, &utc("2031-01-01 00:00")).unwrap().len(), 1);
+  }
+
+  #[test]
+  fn test_claim_reaches_other_replicas() {
+    let (local, other) = (setup().unwrap(), setup().unwrap());
+    let task = insert_task(&local, "Task".to_string()).unwrap();
+    set_task_reminder(&local, task.id, "2030-01-01 08:00").unwrap();
+    sync_databases(&local, &other, false).unwrap();
+
+    assert!(claim_reminder(&local, task.id).unwrap());
+    sync_databases(&local, &other, false).unwrap();
+
+    assert!(read_due_reminders(&other, &utc("2031-01-01 00:00")).unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_clear_task_reminder() {
+    let conn = setup().unwrap();
+    let task = insert_task(&conn, "Task".to_string()).unwrap();
+    set_task_reminder(&conn, task.id, "2030-01-01 08:00").unwrap();
+
+    clear_task_reminder(&conn, task.id).unwrap();
+
+    assert!(read_due_reminders(&conn, &utc("2031-01-01 00:00")).unwrap().is_empty());
+    assert!(clear_task_reminder(&conn, 999).is_err());
+  }
+
+  #[test]
+  fn test_done_and_missing_tasks_are_not_reminded() {
+    let conn = setup().unwrap();
+    let task = insert_task(&conn, "Done task".to_string()).unwrap();
+    set_task_reminder(&conn, task.id, "2030-01-01 08:00").unwrap();
+    update_task_status(&conn, task.id, true).unwrap();
+
+    assert!(read_due_reminders(&conn, &utc("2031-01-01 00:00")).unwrap().is_empty());
+    assert!(set_task_reminder(&conn, 999, "2030-01-01 08:00").is_err());
+  }
+}
@@ -0,0 +1,477 @@
+use chrono::NaiveDate;
+use rusqlite::{Connection, Error, Result, ToSql};
+
+use super::task::Task;
+
+/// Conditions of a `--where` selection, all of which must hold.
+#[derive(Debug, Default, PartialEq)]
+pub struct TaskFilter {
+  pub is_done: Option<bool>,
+  pub name_contains: Option<String>,
+  pub created_before: Option<String>,
+  pub created_after: Option<String>,
+}
+
+impl TaskFilter {
+  /// Whether the live task `task` satisfies every condition, the same way
+  /// the SQL selection does.
+  pub fn matches(&self, task: &Task) -> bool {
+    let created_on = task.created_at.get(..10).unwrap_or(&task.created_at);
+    self.is_done.is_none_or(|is_done| task.is_done == is_done)
+      && self.name_contains.as_ref().is_none_or(|part| name_contains(&task.name, part))
+      && self.created_before.as_ref().is_none_or(|before| created_on < before.as_str())
+      && self.created_after.as_ref().is_none_or(|after| created_on >= after.as_str())
+  }
+}
+
+/// Whether `name` contains `part`, ignoring case in any script.
+fn name_contains(name: &str, part: &str) -> bool {
+  name.to_lowercase().contains(&part.to_lowercase())
+}
+
+fn invalid(message: String) -> Error {
+  Error::InvalidParameterName(message)
+}
+
+/// Shortest UUID prefix accepted to name a task.
+pub const MIN_UUID_PREFIX: usize = 8;
+
+/// Most ids or display indexes a single range may span.
+pub const MAX_RANGE_SPAN: u32 = 10_000;
+
+/// The ways a command can name a task.
+#[derive(Debug, PartialEq)]
+pub enum TaskRef {
+  /// The permanent row id, e.g. `12`.
+  Id(u32),
+  /// The display index among open tasks, e.g. `@3`.
+  Index(u32),
+  /// A full UUID or a prefix of at least `MIN_UUID_PREFIX` characters.
+  /// Prefixes made only of digits parse as ids, and are looked up as
+  /// prefixes when no task has that id.
+  Uuid(String),
+}
+
+/// The UUID prefix an id may also be, when it is long enough.
+fn id_as_uuid_prefix(value: &str) -> Option<&str> {
+  let value = value.trim();
+  (value.len() >= MIN_UUID_PREFIX).then_some(value)
+}
+
+pub fn parse_task_ref(value: &str) -> Result<TaskRef> {
+  let value = value.trim();
+  if let Some(index) = value.strip_prefix('@') {
+    return index.parse().map(TaskRef::Index)
+      .map_err(|_| invalid(format!("Invalid display index '{value}'")));
+  }
+  if let Ok(id) = value.parse() {
+    return Ok(TaskRef::Id(id));
+  }
+  if value.len() >= MIN_UUID_PREFIX && value.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+    return Ok(TaskRef::Uuid(value.to_lowercase()));
+  }
+
+  Err(invalid(format!("Invalid task '{value}', expected an id, an @index or a uuid")))
+}
+
+/// Id of the live task named by `value`, failing with `QueryReturnedNoRows`
+/// when there is none.
+pub fn resolve_task_ref(conn: &Connection, value: &str) -> Result<u32> {
+  match parse_task_ref(value)? {
+    TaskRef::Id(id) => match conn.query_row(
+      "SELECT id FROM tasks WHERE id = ? AND deleted_at IS NULL", [&id], |row| row.get(0),
+    ) {
+      Err(Error::QueryReturnedNoRows) => match id_as_uuid_prefix(value) {
+        Some(prefix) => resolve_uuid_prefix(conn, prefix),
+        None => Err(Error::QueryReturnedNoRows),
+      },
+      result => result,
+    },
+    TaskRef::Index(index) => conn.query_row(
+      "SELECT id FROM tasks WHERE is_done = 0 AND deleted_at IS NULL ORDER BY id LIMIT 1 OFFSET ?",
+      [index.checked_sub(1).ok_or(Error::QueryReturnedNoRows)?],
+      |row| row.get(0),
+    ),
+    TaskRef::Uuid(prefix) => resolve_uuid_prefix(conn, &prefix),
+  }
+}
+
+fn resolve_uuid_prefix(conn: &Connection, prefix: &str) -> Result<u32> {
+  let mut statement = conn.prepare("SELECT id FROM tasks WHERE deleted_at IS NULL AND uuid LIKE ? || '%'")?;
+  let ids = statement.query_map([prefix], |row| row.get(0))?.collect::<Result<Vec<u32>>>()?;
+  match ids.as_slice() {
+    [] => Err(Error::QueryReturnedNoRows),
+    [id] => Ok(*id),
+    _ => Err(invalid(format!("Ambiguous uuid prefix '{prefix}'"))),
+  }
+}
+
+/// Same as `resolve_task_ref`, looking the task up among the live `tasks`
+/// of a store without SQL.
+pub fn find_task_ref(tasks: &[Task], value: &str) -> Result<u32> {
+  let with_prefix = |prefix: &str| tasks.iter().filter(|task| task.uuid.starts_with(prefix)).map(|task| task.id).collect();
+  let found: Vec<u32> = match parse_task_ref(value)? {
+    TaskRef::Id(id) => match tasks.iter().find(|task| task.id == id) {
+      Some(task) => vec![task.id],
+      None => id_as_uuid_prefix(value).map(with_prefix).unwrap_or_default(),
+    },
+    TaskRef::Index(index) => tasks.iter().filter(|task| task.index == Some(index)).map(|task| task.id).collect(),
+    TaskRef::Uuid(prefix) => with_prefix(&prefix),
+  };
+  match found.as_slice() {
+    [] => Err(Error::QueryReturnedNoRows),
+    [id] => Ok(*id),
+    _ => Err(invalid(format!("Ambiguous uuid prefix '{}'", value.trim()))),
+  }
+}
+
+/// An inclusive range of ids, e.g. `3-9`, or of display indexes, e.g. `@1-4`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TaskRange {
+  pub start: u32,
+  pub end: u32,
+  pub is_index: bool,
+}
+
+impl TaskRange {
+  /// Whether the live task `task` falls in the range.
+  pub fn contains(&self, task: &Task) -> bool {
+    let position = if self.is_index { task.index } else { Some(task.id) };
+    position.is_some_and(|position| (self.start..=self.end).contains(&position))
+  }
+}
+
+/// Split `3-9` or `@1-4` into a range. Both bounds must be plain numbers
+/// shorter than a uuid prefix, so `00000001-2345` is not read as `1-2345`.
+fn parse_range(value: &str) -> Option<TaskRange> {
+  let (start, end) = value.split_once('-')?;
+  let (start, end, is_index) = match start.strip_prefix('@') {
+    Some(start) => (start, end.strip_prefix('@').unwrap_or(end), true),
+    None => (start, end, false),
+  };
+  let bound = |bound: &str| match bound.len() < MIN_UUID_PREFIX && bound.bytes().all(|b| b.is_ascii_digit()) {
+    true => bound.parse().ok(),
+    false => None,
+  };
+  Some(TaskRange { start: bound(start)?, end: bound(end)?, is_index })
+}
+
+/// Ids of the live tasks in `range`, in one query.
+fn resolve_task_range(conn: &Connection, range: TaskRange) -> Result<Vec<u32>> {
+  let mut statement = match range.is_index {
+    true => conn.prepare("SELECT id FROM tasks WHERE is_done = 0 AND deleted_at IS NULL ORDER BY id LIMIT ?2 OFFSET ?1")?,
+    false => conn.prepare("SELECT id FROM tasks WHERE deleted_at IS NULL AND id BETWEEN ?1 AND ?2 ORDER BY id")?,
+  };
+  let (start, end) = match range.is_index {
+    // Display indexes start at 1, the query takes an offset and a count
+    true => (range.start.max(1) - 1, (range.end + 1).saturating_sub(range.start.max(1))),
+    false => (range.start, range.end),
+  };
+  let ids = statement.query_map([start, end], |row| row.get(0))?.collect();
+  ids
+}
+
+/// Resolve ids, display indexes, UUIDs and inclusive ranges such as `3-9`
+/// or `@1-4` into a sorted list of distinct live task ids.
+pub fn resolve_task_refs(conn: &Connection, values: &[String]) -> Result<Vec<u32>> {
+  resolve_task_refs_with(values, |value| resolve_task_ref(conn, value), |range| resolve_task_range(conn, range))
+}
+
+/// Expand `values` like `resolve_task_refs`, naming each task with `resolve`
+/// and each range with `resolve_range`. Ranges skip the tasks that no
+/// longer exist, but must name at least one; tasks given one by one must
+/// all exist.
+pub fn resolve_task_refs_with(values: &[String], mut resolve: impl FnMut(&str) -> Result<u32>,
+                              mut resolve_range: impl FnMut(TaskRange) -> Result<Vec<u32>>) -> Result<Vec<u32>> {
+  let mut ids = Vec::new();
+
+  for value in values {
+    let value = value.trim();
+    let range = parse_range(value);
+    // A value long enough to be a uuid prefix names that task first
+    if range.is_some_and(|range| !range.is_index) && value.len() >= MIN_UUID_PREFIX {
+      match resolve(value) {
+        Ok(id) => {
+          ids.push(id);
+          continue;
+        },
+        Err(Error::QueryReturnedNoRows) => {},
+        Err(err) => return Err(err),
+      }
+    }
+    match range {
+      Some(range) if range.start > range.end => {
+        return Err(invalid(format!("Invalid range '{value}', the start is after the end")));
+      },
+      Some(range) if range.end - range.start >= MAX_RANGE_SPAN => {
+        return Err(invalid(format!("Invalid range '{value}', it spans more than {MAX_RANGE_SPAN} tasks")));
+      },
+      Some(range) => {
+        let found = resolve_range(range)?;
+        if found.is_empty() {
+          return Err(Error::QueryReturnedNoRows);
+        }
+        ids.extend(found);
+      },
+      None => ids.push(resolve(value)?),
+    }
+  }
+  ids.sort_unstable();
+  ids.dedup();
+
+  Ok(ids)
+}
+
+fn parse_date(key: &str, value: &str) -> Result<String> {
+  NaiveDate::parse_from_str(value, "%Y-%m-%d")
+    .map(|date| date.format("%Y-%m-%d").to_string())
+    .map_err(|_| invalid(format!("Invalid date '{value}' for '{key}', expected YYYY-MM-DD")))
+}
+
+/// Parse a comma separated list of `key=value` terms. Supported keys are
+/// `done` (true/false), `name` (substring, case insensitive), `before` and
+/// `after` (creation date, YYYY-MM-DD).
+pub fn parse_filter(value: &str) -> Result<TaskFilter> {
+  let mut filter = TaskFilter::default();
+
+  for term in value.split(',').map(str::trim).filter(|term| !term.is_empty()) {
+    let Some((key, value)) = term.split_once('=') else {
+      return Err(invalid(format!("Invalid filter term '{term}', expected key=value")));
+    };
+    let (key, value) = (key.trim(), value.trim());
+    match key {
+      "done" => filter.is_done = Some(match value.to_lowercase().as_str() {
+        "true" => true,
+        "false" => false,
+        _ => return Err(invalid(format!("Invalid value '{value}' for 'done', expected true or false"))),
+      }),
+      "name" => filter.name_contains = Some(value.to_string()),
+      "before" => filter.created_before = Some(parse_date(key, value)?),
+      "after" => filter.created_after = Some(parse_date(key, value)?),
+      _ => return Err(invalid(format!("Unknown filter key '{key}', expected done, name, before or after"))),
+    }
+  }
+
+  if filter == TaskFilter::default() {
+    return Err(invalid("Empty filter".to_string()));
+  }
+
+  Ok(filter)
+}
+
+/// Ids of the live tasks matching `filter`.
+fn filter_task_ids(conn: &Connection, filter: &TaskFilter) -> Result<Vec<u32>> {
+  let mut sql = "SELECT id, name FROM tasks WHERE deleted_at IS NULL".to_string();
+  let mut params: Vec<&dyn ToSql> = Vec::new();
+
+  if let Some(is_done) = &filter.is_done {
+    sql.push_str(" AND is_done = ?");
+    params.push(is_done);
+  }
+  if let Some(before) = &filter.created_before {
+    sql.push_str(" AND date(created_at) < ?");
+    params.push(before);
+  }
+  if let Some(after) = &filter.created_after {
+    sql.push_str(" AND date(created_at) >= ?");
+    params.push(after);
+  }
+  sql.push_str(" ORDER BY id");
+
+  let mut statement = conn.prepare(&sql)?;
+  let rows_iter = statement.query_map(params.as_slice(), |row| Ok((row.get(0)?, row.get::<_, String>(1)?)))?;
+
+  // Names are compared here rather than in SQL, whose lower() only folds ASCII
+  let mut ids = Vec::new();
+  for row in rows_iter {
+    let (id, name) = row?;
+    if filter.name_contains.as_ref().is_none_or(|part| name_contains(&name, part)) {
+      ids.push(id);
+    }
+  }
+
+  Ok(ids)
+}
+
+/// Resolve the tasks a bulk command applies to: the listed `ids`, all of
+/// which must be live, narrowed down by `filter`; or every live task
+/// matching `filter` when no id is given.
+pub fn select_task_ids(conn: &Connection, ids: &[u32], filter: Option<&TaskFilter>) -> Result<Vec<u32>> {
+  let matching = match filter {
+    Some(filter) => Some(filter_task_ids(conn, filter)?),
+    None => None,
+  };
+
+  if ids.is_empty() {
+    return Ok(matching.unwrap_or_default());
+  }
+
+  for id in ids {
+    conn.query_row("SELECT id FROM tasks WHERE id = ? AND deleted_at IS NULL", [id], |row| row.get::<_, u32>(0))?;
+  }
+
+  Ok(match matching {
+    Some(matching) => ids.iter().copied().filter(|id| matching.contains(id)).collect(),
+    None => ids.to_vec(),
+  })
+}
+
+/// Same as `select_task_ids`, over the live `tasks` of a store.
+pub fn select_from_tasks(tasks: &[Task], ids: &[u32], filter: Option<&TaskFilter>) -> Result<Vec<u32>> {
+  if ids.iter().any(|id| !tasks.iter().any(|task| task.id == *id)) {
+    return Err(Error::QueryReturnedNoRows);
+  }
+  if ids.is_empty() && filter.is_none() {
+    return Ok(Vec::new());
+  }
+  let selected = |task: &&Task| (ids.is_empty() || ids.contains(&task.id)) && filter.is_none_or(|filter| filter.matches(task));
+
+  let mut selected_ids: Vec<u32> = tasks.iter().filter(selected).map(|task| task.id).collect();
+  if !ids.is_empty() {
+    // Keep the order the ids were given in, like the SQL selection
+    selected_ids.sort_by_key(|id| ids.iter().position(|given| given == id));
+  }
+
+  Ok(selected_ids)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::connect_db;
+  use super::super::task::{delete_task, insert_task, read_tasks, update_task_status};
+
+  fn setup() -> Result<Connection>{
+    // Set up test environment
+    connect_db(Some(true), None)
+  }
+
+  fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|value| value.to_string()).collect()
+  }
+
+  #[test]
+  fn test_parse_task_ref() {
+    assert_eq!(parse_task_ref("12").unwrap(), TaskRef::Id(12));
+    assert_eq!(parse_task_ref("@3").unwrap(), TaskRef::Index(3));
+    assert_eq!(parse_task_ref("0A1B2C3D-4e").unwrap(), TaskRef::Uuid("0a1b2c3d-4e".to_string()));
+    assert!(parse_task_ref("abc").is_err());
+    assert!(parse_task_ref("@x").is_err());
+  }
+
+  #[test]
+  fn test_resolve_ids_indexes_uuids_and_ranges() {
+    let conn = setup().unwrap();
+    for index in 1..=7 {
+      insert_task(&conn, format!("Task {index}")).unwrap();
+    }
+    update_task_status(&conn, 1, true).unwrap();
+    let uuid = read_tasks(&conn).unwrap()[6].uuid.clone();
+
+    assert_eq!(resolve_task_refs(&conn, &strings(&["7", "3-5", "4"])).unwrap(), vec![3, 4, 5, 7]);
+    assert_eq!(resolve_task_refs(&conn, &strings(&["@1", "@3-@4"])).unwrap(), vec![2, 4, 5]);
+    assert_eq!(resolve_task_ref(&conn, &uuid).unwrap(), 7);
+    assert_eq!(resolve_task_ref(&conn, &uuid[..MIN_UUID_PREFIX]).unwrap(), 7);
+    assert!(resolve_task_refs(&conn, &strings(&["9-3"])).is_err());
+
+    // Ranges skip deleted tasks, explicit ids do not
+    delete_task(&conn, 4).unwrap();
+    assert_eq!(resolve_task_refs(&conn, &strings(&["3-5"])).unwrap(), vec![3, 5]);
+    assert!(matches!(resolve_task_refs(&conn, &strings(&["3", "4"])), Err(Error::QueryReturnedNoRows)));
+    assert!(matches!(resolve_task_refs(&conn, &strings(&["8-9"])), Err(Error::QueryReturnedNoRows)));
+    assert!(matches!(resolve_task_ref(&conn, "@7"), Err(Error::QueryReturnedNoRows)));
+    assert!(matches!(resolve_task_ref(&conn, "@0"), Err(Error::QueryReturnedNoRows)));
+  }
+
+  #[test]
+  fn test_all_digit_uuid_prefix_without_such_id() {
+    let conn = setup().unwrap();
+    let task = insert_task(&conn, "Digits".to_string()).unwrap();
+    conn.execute("UPDATE tasks SET uuid = '12345678-9abc-4def-8000-000000000000' WHERE id = ?", [task.id]).unwrap();
+    let tasks = read_tasks(&conn).unwrap();
+
+    assert_eq!(resolve_task_ref(&conn, "12345678").unwrap(), task.id);
+    assert_eq!(find_task_ref(&tasks, "12345678").unwrap(), task.id);
+    assert_eq!(resolve_task_ref(&conn, "1").unwrap(), task.id, "Ids still come first");
+    assert!(matches!(resolve_task_ref(&conn, "1234"), Err(Error::QueryReturnedNoRows)));
+  }
+
+  #[test]
+  fn test_digit_uuid_prefix_with_a_dash_is_not_a_range() {
+    let conn = setup().unwrap();
+    for index in 1..=3 {
+      insert_task(&conn, format!("Task {index}")).unwrap();
+    }
+    conn.execute("UPDATE tasks SET uuid = '00000001-2345-4def-8000-000000000000' WHERE id = 3", []).unwrap();
+
+    assert_eq!(resolve_task_refs(&conn, &strings(&["00000001-2345"])).unwrap(), vec![3]);
+    assert!(matches!(resolve_task_refs(&conn, &strings(&["00000002-2345"])), Err(Error::QueryReturnedNoRows)));
+    assert_eq!(resolve_task_refs(&conn, &strings(&["1-2345"])).unwrap(), vec![1, 2, 3]);
+  }
+
+  #[test]
+  fn test_ranges_are_capped() {
+    let conn = setup().unwrap();
+    insert_task(&conn, "Task".to_string()).unwrap();
+
+    assert!(matches!(resolve_task_refs(&conn, &strings(&["1-4000000"])), Err(Error::InvalidParameterName(_))));
+    assert!(matches!(resolve_task_refs(&conn, &strings(&["@1-@10001"])), Err(Error::InvalidParameterName(_))));
+    assert_eq!(resolve_task_refs(&conn, &strings(&["1-10000", "@0-@2"])).unwrap(), vec![1]);
+  }
+
+  #[test]
+  fn test_parse_filter() {
+    let filter = parse_filter("done=false, name=milk, after=2030-01-01").unwrap();
+
+    assert_eq!(filter, TaskFilter {
+      is_done: Some(false),
+      name_contains: Some("milk".to_string()),
+      created_before: None,
+      created_after: Some("2030-01-01".to_string()),
+    });
+    assert!(parse_filter("done=maybe").is_err());
+    assert!(parse_filter("color=red").is_err());
+    assert!(parse_filter("").is_err());
+  }
+
+  #[test]
+  fn test_select_by_filter_and_ids() {
+    let conn = setup().unwrap();
+    let milk = insert_task(&conn, "Buy milk".to_string()).unwrap();
+    let bread = insert_task(&conn, "Buy bread".to_string()).unwrap();
+    let report = insert_task(&conn, "Write report".to_string()).unwrap();
+    let deleted = insert_task(&conn, "Buy cheese".to_string()).unwrap();
+    update_task_status(&conn, bread.id, true).unwrap();
+    delete_task(&conn, deleted.id).unwrap();
+
+    let buy = parse_filter("name=buy").unwrap();
+    assert_eq!(select_task_ids(&conn, &[], Some(&buy)).unwrap(), vec![milk.id, bread.id]);
+
+    let open = parse_filter("done=false").unwrap();
+    assert_eq!(select_task_ids(&conn, &[milk.id, bread.id], Some(&open)).unwrap(), vec![milk.id]);
+    assert_eq!(select_task_ids(&conn, &[report.id], None).unwrap(), vec![report.id]);
+
+    // `%` and `_` are plain characters, as in `TaskFilter::matches`
+    let discount = insert_task(&conn, "Buy 50% off_cut".to_string()).unwrap();
+    let percent = parse_filter("name=50%").unwrap();
+    assert_eq!(select_task_ids(&conn, &[], Some(&percent)).unwrap(), vec![discount.id]);
+    assert!(select_task_ids(&conn, &[], Some(&parse_filter("name=b_y").unwrap())).unwrap().is_empty());
+    assert!(percent.matches(&discount));
+
+    // Case is folded beyond ASCII, as in `TaskFilter::matches`
+    let school = insert_task(&conn, "Walk to école".to_string()).unwrap();
+    let accented = parse_filter("name=ÉCOLE").unwrap();
+    assert_eq!(select_task_ids(&conn, &[], Some(&accented)).unwrap(), vec![school.id]);
+    assert!(accented.matches(&school));
+  }
+
+  #[test]
+  fn test_select_missing_or_deleted_id_fails() {
+    let conn = setup().unwrap();
+    let task = insert_task(&conn, "Deleted".to_string()).unwrap();
+    delete_task(&conn, task.id).unwrap();
+
+    assert!(matches!(select_task_ids(&conn, &[task.id], None), Err(Error::QueryReturnedNoRows)));
+    assert!(matches!(select_task_ids(&conn, &[999], None), Err(Error::QueryReturnedNoRows)));
+  }
+}
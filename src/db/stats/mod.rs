@@ -0,0 +1,205 @@
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, NaiveDate};
+use rusqlite::{Connection, Result};
+
+use super::task::{task_from_row, Task, TASK_COLUMNS};
+
+/// Number of oldest open tasks listed in the report.
+pub const OLDEST_OPEN_LIMIT: u32 = 5;
+
+/// Productivity figures over the live (not deleted) tasks.
+pub struct Stats {
+  pub total: u32,
+  pub open: u32,
+  pub done: u32,
+  /// Done tasks over all tasks, between 0 and 1.
+  pub completion_rate: f64,
+  /// Mean seconds from `created_at` to `completed_at`, when known.
+  pub average_completion_seconds: Option<f64>,
+  pub completed_per_day: Vec<(String, u32)>,
+  /// Completions per ISO 8601 week, such as `2026-W53`.
+  pub completed_per_week: Vec<(String, u32)>,
+  pub oldest_open: Vec<Task>,
+}
+
+fn count_by(conn: &Connection, bucket: &str) -> Result<Vec<(String, u32)>> {
+  let sql = format!(
+    "SELECT {bucket} AS bucket, COUNT(*) FROM tasks
+      WHERE deleted_at IS NULL AND is_done = 1 AND completed_at IS NOT NULL
+      GROUP BY bucket ORDER BY bucket"
+  );
+  let mut statement = conn.prepare(&sql)?;
+  let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+  let mut counts = Vec::new();
+  for row in rows {
+    counts.push(row?);
+  }
+
+  Ok(counts)
+}
+
+/// Fold daily counts into ISO 8601 weeks, which start on Monday and belong
+/// to the year holding their Thursday, so no week is split at New Year.
+fn count_by_week(per_day: &[(String, u32)]) -> Vec<(String, u32)> {
+  let mut weeks = BTreeMap::new();
+  for (day, count) in per_day {
+    let week = match NaiveDate::parse_from_str(day, "%Y-%m-%d") {
+      Ok(date) => format!("{}-W{:02}", date.iso_week().year(), date.iso_week().week()),
+      Err(_) => day.clone(),
+    };
+    *weeks.entry(week).or_insert(0) += count;
+  }
+
+  weeks.into_iter().collect()
+}
+
+pub fn compute_stats(conn: &Connection) -> Result<Stats> {
+  let (total, done): (u32, u32) = conn.query_row(
+    "SELECT COUNT(*), COALESCE(SUM(is_done), 0) FROM tasks WHERE deleted_at IS NULL",
+    [],
+    |row| Ok((row.get(0)?, row.get(1)?)),
+  )?;
+
+  let average_completion_seconds: Option<f64> = conn.query_row(
+    "SELECT AVG((julianday(completed_at) - julianday(created_at)) * 86400) FROM tasks
+      WHERE deleted_at IS NULL AND is_done = 1 AND completed_at IS NOT NULL",
+    [],
+    |row| row.get(0),
+  )?;
+
+  let mut statement = conn.prepare(&format!(
+    "SELECT {TASK_COLUMNS} FROM tasks
+      WHERE deleted_at IS NULL AND is_done = 0 ORDER BY created_at, id LIMIT ?"
+  ))?;
+  let oldest_iter = statement.query_map([OLDEST_OPEN_LIMIT], task_from_row)?;
+  let mut oldest_open = Vec::new();
+  for task in oldest_iter {
+    oldest_open.push(task?);
+  }
+
+  let completed_per_day = count_by(conn, "date(completed_at)")?;
+
+  Ok(Stats {
+    total,
+    open: total - done,
+    done,
+    completion_rate: if total == 0 { 0.0 } else { f64::from(done) / f64::from(total) },
+    average_completion_seconds,
+    completed_per_week: count_by_week(&completed_per_day),
+    completed_per_day,
+    oldest_open,
+  })
+}
+
+/// Render seconds as a short human duration such as `2d 3h` or `45m`.
+pub fn format_seconds(seconds: f64) -> String {
+  let seconds = seconds.max(0.0).round() as u64;
+  let (days, hours, minutes) = (seconds / 86400, (seconds % 86400) / 3600, (seconds % 3600) / 60);
+  match (days, hours) {
+    (0, 0) => format!("{minutes}m"),
+    (0, _) => format!("{hours}h {minutes}m"),
+    _ => format!("{days}d {hours}h"),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::connect_db;
+  use super::super::task::{delete_task, insert_task, update_task_status};
+
+  fn setup() -> Result<Connection>{
+    // Set up test environment
+    connect_db(Some(true), None)
+  }
+
+  fn set_times(conn: &Connection, id: u32, created_at: &str, completed_at: &str) {
+    conn.execute(
+      "UPDATE tasks SET created_at = ?, completed_at = ? WHERE id = ?",
+      [&created_at as &dyn rusqlite::ToSql, &completed_at, &id],
+    ).unwrap();
+  }
+
+  #[test]
+  fn test_stats_on_empty_database() {
+    let conn = setup().unwrap();
+
+    let stats = compute_stats(&conn).unwrap();
+
+    assert_eq!(stats.total, 0);
+    assert_eq!(stats.completion_rate, 0.0);
+    assert!(stats.average_completion_seconds.is_none());
+    assert!(stats.oldest_open.is_empty());
+  }
+
+  #[test]
+  fn test_stats_counts_and_rates() {
+    let conn = setup().unwrap();
+    let first = insert_task(&conn, "First".to_string()).unwrap();
+    let second = insert_task(&conn, "Second".to_string()).unwrap();
+    insert_task(&conn, "Third".to_string()).unwrap();
+    let deleted = insert_task(&conn, "Deleted".to_string()).unwrap();
+    update_task_status(&conn, first.id, true).unwrap();
+    update_task_status(&conn, second.id, true).unwrap();
+    delete_task(&conn, deleted.id).unwrap();
+    set_times(&conn, first.id, "2030-01-01 00:00:00", "2030-01-01 12:00:00");
+    set_times(&conn, second.id, "2030-01-01 00:00:00", "2030-01-02 12:00:00");
+
+    let stats = compute_stats(&conn).unwrap();
+
+    assert_eq!((stats.total, stats.open, stats.done), (3, 1, 2));
+    assert!((stats.completion_rate - 2.0 / 3.0).abs() < 1e-9);
+    assert!((stats.average_completion_seconds.unwrap() - 86400.0).abs() < 1.0);
+    assert_eq!(stats.completed_per_day, vec![
+      ("2030-01-01".to_string(), 1),
+      ("2030-01-02".to_string(), 1),
+    ]);
+    assert_eq!(stats.completed_per_week, vec![("2030-W01".to_string(), 2)]);
+    assert_eq!(stats.oldest_open.len(), 1);
+    assert_eq!(stats.oldest_open[0].name, "Third");
+  }
+
+  #[test]
+  fn test_weeks_follow_iso_numbering_across_new_year() {
+    let conn = setup().unwrap();
+    let days = ["2026-12-28 09:00:00", "2026-12-31 09:00:00", "2027-01-01 09:00:00", "2027-01-04 09:00:00"];
+    for day in days {
+      let task = insert_task(&conn, day.to_string()).unwrap();
+      update_task_status(&conn, task.id, true).unwrap();
+      set_times(&conn, task.id, day, day);
+    }
+
+    let stats = compute_stats(&conn).unwrap();
+
+    assert_eq!(stats.completed_per_week, vec![
+      ("2026-W53".to_string(), 3),
+      ("2027-W01".to_string(), 1),
+    ]);
+  }
+
+  #[test]
+  fn test_oldest_open_tasks_are_sorted_and_limited() {
+    let conn = setup().unwrap();
+    for index in 0..OLDEST_OPEN_LIMIT + 2 {
+      let task = insert_task(&conn, format!("Task {index}")).unwrap();
+      conn.execute(
+        "UPDATE tasks SET created_at = ? WHERE id = ?",
+        [&format!("2030-01-{:02} 00:00:00", 20 - index) as &dyn rusqlite::ToSql, &task.id],
+      ).unwrap();
+    }
+
+    let stats = compute_stats(&conn).unwrap();
+
+    assert_eq!(stats.oldest_open.len() as u32, OLDEST_OPEN_LIMIT);
+    assert_eq!(stats.oldest_open[0].name, format!("Task {}", OLDEST_OPEN_LIMIT + 1));
+  }
+
+  #[test]
+  fn test_format_seconds() {
+    assert_eq!(format_seconds(59.0 * 60.0), "59m");
+    assert_eq!(format_seconds(3.0 * 3600.0 + 120.0), "3h 2m");
+    assert_eq!(format_seconds(2.0 * 86400.0 + 3.0 * 3600.0), "2d 3h");
+  }
+}
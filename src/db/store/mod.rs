@@ -0,0 +1,427 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::error;
+use std::fmt;
+use std::io;
+
+use chrono::Utc;
+use rusqlite::Connection;
+use uuid::Uuid;
+
+use super::with_transaction;
+use super::selection::{
+  find_task_ref, resolve_task_refs, resolve_task_refs_with, select_from_tasks, select_task_ids, TaskFilter, TaskRange,
+};
+use super::task::{
+  changed_since, delete_tasks, import_tasks, insert_task, read_all_tasks, read_task, read_tasks,
+  read_tasks_completed_since, restore_task, update_tasks_status, Task,
+};
+
+/// Why a `TaskStore` operation failed.
+#[derive(Debug)]
+pub enum StoreError {
+  /// The task does not exist, or is not live when it has to be.
+  NotFound,
+  /// Rejected input, such as an empty name or an ambiguous uuid prefix.
+  Invalid(String),
+  /// The SQLite database failed.
+  Sqlite(rusqlite::Error),
+  /// A file of the storage could not be read or written.
+  Io(io::Error),
+}
+
+pub type StoreResult<T> = Result<T, StoreError>;
+
+impl fmt::Display for StoreError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      StoreError::NotFound => write!(f, "Task not found"),
+      StoreError::Invalid(message) => write!(f, "{message}"),
+      StoreError::Sqlite(err) => write!(f, "{err}"),
+      StoreError::Io(err) => write!(f, "{err}"),
+    }
+  }
+}
+
+impl error::Error for StoreError {
+  fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+    match self {
+      StoreError::Sqlite(err) => Some(err),
+      StoreError::Io(err) => Some(err),
+      _ => None,
+    }
+  }
+}
+
+impl From<rusqlite::Error> for StoreError {
+  fn from(err: rusqlite::Error) -> StoreError {
+    match err {
+      rusqlite::Error::QueryReturnedNoRows => StoreError::NotFound,
+      rusqlite::Error::InvalidParameterName(message) => StoreError::Invalid(message),
+      err => StoreError::Sqlite(err),
+    }
+  }
+}
+
+impl From<io::Error> for StoreError {
+  fn from(err: io::Error) -> StoreError {
+    StoreError::Io(err)
+  }
+}
+
+/// Where tasks are kept. Commands only go through this trait, so other
+/// storages can be plugged in.
+pub trait TaskStore: Any {
+  fn insert(&self, name: String) -> StoreResult<Task>;
+
+  /// Live tasks, oldest first, with their display index.
+  fn list(&self) -> StoreResult<Vec<Task>>;
+
+  /// The task `id`, deleted or not.
+  fn get(&self, id: u32) -> StoreResult<Task>;
+
+  /// Set the status of every live task in `ids`, leaving all of them
+  /// untouched if any is missing or deleted.
+  fn update(&self, ids: &[u32], is_done: bool, completed_by: Option<&str>) -> StoreResult<Vec<Task>>;
+
+  /// Soft-delete every live task in `ids`, all or none.
+  fn delete(&self, ids: &[u32]) -> StoreResult<Vec<Task>>;
+
+  /// Bring back the deleted task `id`.
+  fn restore(&self, id: u32) -> StoreResult<Task>;
+
+  /// Every task, deleted ones included, oldest first.
+  fn all(&self) -> StoreResult<Vec<Task>>;
+
+  /// Every task, deleted ones included, changed after `since`, oldest change first.
+  fn changed_since(&self, since: &str) -> StoreResult<Vec<Task>>;
+
+  /// Add `tasks` as they are, keeping their ids, UUIDs and timestamps, e.g.
+  /// when moving to another storage.
+  fn import(&self, tasks: &[Task]) -> StoreResult<()>;
+
+  /// Live tasks completed at or after `since`, oldest completion first.
+  fn completed_since(&self, since: &str) -> StoreResult<Vec<Task>> {
+    let mut tasks: Vec<Task> = self.list()?.into_iter()
+      .filter(|task| task.is_done && task.completed_at.as_deref().is_some_and(|completed_at| completed_at >= since))
+      .collect();
+    tasks.sort_by(|a, b| (&a.completed_at, a.id).cmp(&(&b.completed_at, b.id)));
+    Ok(tasks)
+  }
+
+  /// Ids of the live tasks named by ids, display indexes, UUIDs and ranges.
+  fn resolve(&self, values: &[String]) -> StoreResult<Vec<u32>> {
+    let tasks = self.list()?;
+    let in_range = |range: TaskRange| Ok(tasks.iter().filter(|task| range.contains(task)).map(|task| task.id).collect());
+    Ok(resolve_task_refs_with(values, |value| find_task_ref(&tasks, value), in_range)?)
+  }
+
+  /// The listed live `ids` narrowed down by `filter`, or every live task
+  /// matching `filter` when no id is given.
+  fn select(&self, ids: &[u32], filter: Option<&TaskFilter>) -> StoreResult<Vec<u32>> {
+    Ok(select_from_tasks(&self.list()?, ids, filter)?)
+  }
+}
+
+impl dyn TaskStore {
+  /// The SQLite connection behind the store, needed by the commands that
+  /// go beyond tasks, such as users, timers, backups and sync.
+  pub(crate) fn connection(&self) -> Option<&Connection> {
+    (self as &dyn Any).downcast_ref()
+  }
+}
+
+/// The default storage, a SQLite database.
+impl TaskStore for Connection {
+  fn insert(&self, name: String) -> StoreResult<Task> {
+    Ok(insert_task(self, name)?)
+  }
+
+  fn list(&self) -> StoreResult<Vec<Task>> {
+    Ok(read_tasks(self)?)
+  }
+
+  fn get(&self, id: u32) -> StoreResult<Task> {
+    Ok(read_task(self, id)?)
+  }
+
+  fn update(&self, ids: &[u32], is_done: bool, completed_by: Option<&str>) -> StoreResult<Vec<Task>> {
+    Ok(with_transaction(self, |conn| {
+      select_task_ids(conn, ids, None)?;
+      update_tasks_status(conn, ids, is_done, completed_by)
+    })?)
+  }
+
+  fn delete(&self, ids: &[u32]) -> StoreResult<Vec<Task>> {
+    Ok(delete_tasks(self, ids)?)
+  }
+
+  fn restore(&self, id: u32) -> StoreResult<Task> {
+    Ok(restore_task(self, id)?)
+  }
+
+  fn all(&self) -> StoreResult<Vec<Task>> {
+    Ok(read_all_tasks(self)?)
+  }
+
+  fn changed_since(&self, since: &str) -> StoreResult<Vec<Task>> {
+    Ok(changed_since(self, since)?)
+  }
+
+  fn import(&self, tasks: &[Task]) -> StoreResult<()> {
+    Ok(import_tasks(self, tasks)?)
+  }
+
+  fn completed_since(&self, since: &str) -> StoreResult<Vec<Task>> {
+    Ok(read_tasks_completed_since(self, since)?)
+  }
+
+  fn resolve(&self, values: &[String]) -> StoreResult<Vec<u32>> {
+    Ok(resolve_task_refs(self, values)?)
+  }
+
+  fn select(&self, ids: &[u32], filter: Option<&TaskFilter>) -> StoreResult<Vec<u32>> {
+    Ok(select_task_ids(self, ids, filter)?)
+  }
+}
+
+/// Tasks kept in a `Vec`, lost when dropped. Fast to set up in tests and
+/// a starting point for other storages.
+#[derive(Default)]
+pub struct MemoryStore {
+  tasks: RefCell<Vec<Task>>,
+}
+
+impl MemoryStore {
+  pub fn new() -> MemoryStore {
+    MemoryStore::default()
+  }
+
+  /// A store holding `tasks`, deleted ones included.
+  pub fn from_tasks(tasks: Vec<Task>) -> MemoryStore {
+    MemoryStore { tasks: RefCell::new(tasks) }
+  }
+
+  /// Every task, deleted ones included, oldest first.
+  pub fn into_tasks(self) -> Vec<Task> {
+    self.tasks.into_inner()
+  }
+
+  /// Copies of the tasks matching `keep`, with the display index the
+  /// SQLite storage would give them.
+  fn snapshot(&self, keep: impl Fn(&Task) -> bool) -> Vec<Task> {
+    let mut index = 0;
+    let mut tasks = Vec::new();
+    for task in self.tasks.borrow().iter() {
+      let mut task = task.clone();
+      task.index = None;
+      if !task.is_done && task.deleted_at.is_none() {
+        index += 1;
+        task.index = Some(index);
+      }
+      if keep(&task) {
+        tasks.push(task);
+      }
+    }
+    tasks
+  }
+
+  fn find(&self, id: u32) -> StoreResult<Task> {
+    self.snapshot(|task| task.id == id).pop().ok_or(StoreError::NotFound)
+  }
+
+  /// Fail unless every task in `ids` is live.
+  fn ensure_live(&self, ids: &[u32]) -> StoreResult<()> {
+    let tasks = self.tasks.borrow();
+    for id in ids {
+      if !tasks.iter().any(|task| task.id == *id && task.deleted_at.is_none()) {
+        return Err(StoreError::NotFound);
+      }
+    }
+    Ok(())
+  }
+
+  /// Apply `change` to the task `id` and bump its `updated_at`.
+  fn modify(&self, id: u32, change: impl FnOnce(&mut Task)) -> StoreResult<Task> {
+    if let Some(task) = self.tasks.borrow_mut().iter_mut().find(|task| task.id == id) {
+      change(task);
+      task.updated_at = now_millis();
+    }
+    self.find(id)
+  }
+}
+
+/// The current UTC time as SQLite's `CURRENT_TIMESTAMP` stores it.
+fn now() -> String {
+  Utc::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// The current UTC time as `updated_at` stores it.
+fn now_millis() -> String {
+  Utc::now().format("%Y-%m-%d %H:%M:%S%.3f").to_string()
+}
+
+impl TaskStore for MemoryStore {
+  fn insert(&self, name: String) -> StoreResult<Task> {
+    if name.is_empty() {
+      return Err(StoreError::Invalid("Task name cannot be empty".to_string()));
+    }
+    let id = self.tasks.borrow().iter().map(|task| task.id).max().unwrap_or(0) + 1;
+    self.tasks.borrow_mut().push(Task {
+      id,
+      name,
+      is_done: false,
+      created_at: now(),
+      completed_at: None,
+      completed_by: None,
+      uuid: Uuid::new_v4().to_string(),
+      updated_at: now_millis(),
+      deleted_at: None,
+      index: None,
+    });
+    self.find(id)
+  }
+
+  fn list(&self) -> StoreResult<Vec<Task>> {
+    Ok(self.snapshot(|task| task.deleted_at.is_none()))
+  }
+
+  fn get(&self, id: u32) -> StoreResult<Task> {
+    self.find(id)
+  }
+
+  fn update(&self, ids: &[u32], is_done: bool, completed_by: Option<&str>) -> StoreResult<Vec<Task>> {
+    self.ensure_live(ids)?;
+
+    ids.iter().map(|id| self.modify(*id, |task| {
+      // Keep the first completion when a done task is marked done again
+      if is_done && !task.is_done {
+        task.completed_at = Some(now());
+        task.completed_by = completed_by.map(str::to_string);
+      } else if !is_done {
+        task.completed_at = None;
+        task.completed_by = None;
+      }
+      task.is_done = is_done;
+    })).collect()
+  }
+
+  fn delete(&self, ids: &[u32]) -> StoreResult<Vec<Task>> {
+    self.ensure_live(ids)?;
+
+    ids.iter().map(|id| self.modify(*id, |task| task.deleted_at = Some(now()))).collect()
+  }
+
+  fn restore(&self, id: u32) -> StoreResult<Task> {
+    if self.find(id)?.deleted_at.is_none() {
+      return Err(StoreError::NotFound);
+    }
+
+    self.modify(id, |task| task.deleted_at = None)
+  }
+
+  fn all(&self) -> StoreResult<Vec<Task>> {
+    Ok(self.snapshot(|_| true))
+  }
+
+  fn changed_since(&self, since: &str) -> StoreResult<Vec<Task>> {
+    let mut tasks = self.snapshot(|task| task.updated_at.as_str() > since);
+    tasks.sort_by(|a, b| (&a.updated_at, a.id).cmp(&(&b.updated_at, b.id)));
+    Ok(tasks)
+  }
+
+  fn import(&self, tasks: &[Task]) -> StoreResult<()> {
+    self.tasks.borrow_mut().extend(tasks.iter().cloned());
+    Ok(())
+  }
+}
+
+/// Copy every task, deleted ones included, from `from` into the empty
+/// storage `to`. Returns how many tasks were copied.
+pub fn migrate_tasks(from: &dyn TaskStore, to: &dyn TaskStore) -> StoreResult<usize> {
+  if !to.all()?.is_empty() {
+    return Err(StoreError::Invalid("The destination storage already has tasks".to_string()));
+  }
+
+  let tasks = from.all()?;
+  to.import(&tasks)?;
+
+  Ok(tasks.len())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::connect_db;
+  use super::super::selection::parse_filter;
+
+  fn strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|value| value.to_string()).collect()
+  }
+
+  /// The same scenario must hold for every storage.
+  fn check_store(store: &dyn TaskStore) {
+    let milk = store.insert("Buy milk".to_string()).unwrap();
+    let bread = store.insert("Buy bread".to_string()).unwrap();
+    let report = store.insert("Write report".to_string()).unwrap();
+    assert!(store.insert(String::new()).is_err());
+
+    let done = store.update(&[bread.id], true, Some("ana")).unwrap();
+    assert_eq!(done[0].completed_by.as_deref(), Some("ana"));
+    assert!(matches!(store.update(&[bread.id, 999], false, None), Err(StoreError::NotFound)));
+    assert!(store.get(bread.id).unwrap().is_done, "A failed update changes nothing");
+
+    assert_eq!(store.resolve(&strings(&["@2", &milk.uuid[..8]])).unwrap(), vec![milk.id, report.id]);
+    let buy = parse_filter("name=BUY,done=false").unwrap();
+    assert_eq!(store.select(&[], Some(&buy)).unwrap(), vec![milk.id]);
+    assert_eq!(store.completed_since("2000-01-01").unwrap().len(), 1);
+
+    assert_eq!(store.all().unwrap().len(), 3);
+    store.delete(&[milk.id]).unwrap();
+    assert!(matches!(store.delete(&[milk.id]), Err(StoreError::NotFound)));
+    assert!(matches!(store.update(&[milk.id], true, None), Err(StoreError::NotFound)), "Deleted tasks keep their status");
+    let names: Vec<String> = store.list().unwrap().into_iter().map(|task| task.name).collect();
+    assert_eq!(names, vec!["Buy bread", "Write report"]);
+    assert_eq!(store.list().unwrap()[1].index, Some(1));
+    assert!(store.changed_since("2000-01-01").unwrap().iter().any(|task| task.deleted_at.is_some()));
+
+    assert!(store.restore(milk.id).unwrap().deleted_at.is_none());
+    assert!(matches!(store.restore(milk.id), Err(StoreError::NotFound)));
+    assert_eq!(store.list().unwrap().len(), 3);
+  }
+
+  #[test]
+  fn test_sqlite_store() {
+    let conn = connect_db(Some(true), None).unwrap();
+    check_store(&conn);
+    assert!((&conn as &dyn TaskStore).connection().is_some());
+  }
+
+  #[test]
+  fn test_memory_store() {
+    let store = MemoryStore::new();
+    check_store(&store);
+    assert!((&store as &dyn TaskStore).connection().is_none());
+  }
+
+  #[test]
+  fn test_migrate_keeps_ids_uuids_and_tombstones() {
+    let conn = connect_db(Some(true), None).unwrap();
+    let kept = conn.insert("Kept".to_string()).unwrap();
+    let deleted = conn.insert("Deleted".to_string()).unwrap();
+    conn.update(&[kept.id], true, Some("ana")).unwrap();
+    conn.delete(&[deleted.id]).unwrap();
+
+    let store = MemoryStore::new();
+    assert_eq!(migrate_tasks(&conn, &store).unwrap(), 2);
+    assert_eq!(store.get(kept.id).unwrap().uuid, kept.uuid);
+    assert_eq!(store.get(kept.id).unwrap().completed_by.as_deref(), Some("ana"));
+    assert!(store.get(deleted.id).unwrap().deleted_at.is_some());
+
+    // And back into an empty database, but never over existing tasks
+    let copy = connect_db(Some(true), None).unwrap();
+    assert_eq!(migrate_tasks(&store, &copy).unwrap(), 2);
+    assert_eq!(copy.list().unwrap()[0].uuid, kept.uuid);
+    assert!(migrate_tasks(&store, &copy).is_err());
+  }
+}
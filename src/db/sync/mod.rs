@@ -0,0 +1,552 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use rusqlite::{Connection, Error, OpenFlags, OptionalExtension, Result, ToSql};
+
+use super::{build_db, configure_connection, with_transaction};
+use super::task::UPDATED_AT_NOW;
+
+/// One of the two databases being merged.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+  Local,
+  Other,
+}
+
+impl fmt::Display for Side {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Side::Local => write!(f, "local"),
+      Side::Other => write!(f, "other"),
+    }
+  }
+}
+
+/// What a sync does to one task on one side.
+#[derive(Debug, PartialEq)]
+pub enum ChangeKind {
+  Added,
+  Deleted,
+  Restored,
+  /// The fields that change, as `field = new value`.
+  Updated(Vec<String>),
+}
+
+#[derive(Debug)]
+pub struct SyncChange {
+  pub uuid: String,
+  pub name: String,
+  pub kind: ChangeKind,
+}
+
+impl fmt::Display for SyncChange {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match &self.kind {
+      ChangeKind::Added => write!(f, "+ {} {} (added)", self.uuid, self.name),
+      ChangeKind::Deleted => write!(f, "- {} {} (deleted)", self.uuid, self.name),
+      ChangeKind::Restored => write!(f, "+ {} {} (restored)", self.uuid, self.name),
+      ChangeKind::Updated(fields) => write!(f, "~ {} {} ({})", self.uuid, self.name, fields.join(", ")),
+    }
+  }
+}
+
+/// A field both sides changed since they last synced. The side with the
+/// newest change wins.
+#[derive(Debug)]
+pub struct Conflict {
+  pub uuid: String,
+  pub name: String,
+  pub field: &'static str,
+  pub local: String,
+  pub other: String,
+  pub winner: Side,
+}
+
+impl fmt::Display for Conflict {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "! {} {}: {} local = {}, other = {}, kept {}",
+           self.uuid, self.name, self.field, self.local, self.other, self.winner)
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct SyncReport {
+  pub into_local: Vec<SyncChange>,
+  pub into_other: Vec<SyncChange>,
+  pub conflicts: Vec<Conflict>,
+}
+
+/// The replicated part of a task, with the clocks deciding each field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncRow {
+  pub uuid: String,
+  pub name: String,
+  pub created_at: String,
+  pub is_done: bool,
+  pub completed_at: Option<String>,
+  pub completed_by: Option<String>,
+  pub remind_at: Option<String>,
+  /// When the reminder fired, so it fires on one replica only.
+  pub reminded_at: Option<String>,
+  pub deleted_at: Option<String>,
+  pub updated_at: String,
+  pub name_updated_at: String,
+  pub status_updated_at: String,
+  pub remind_updated_at: String,
+  /// When the task was last deleted or restored.
+  pub deleted_updated_at: String,
+}
+
+pub fn build_sync_db_table (conn: &Connection) -> Result<()> {
+  conn.execute(
+    "CREATE TABLE IF NOT EXISTS sync_peers (
+      peer TEXT PRIMARY KEY,
+      synced_at DATETIME NOT NULL
+    )",
+    [],
+  )?;
+
+  Ok(())
+}
+
+/// Open an existing database to sync with, migrating it to the current schema.
+pub fn open_other_db(path: &str) -> Result<Connection> {
+  let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_WRITE)?;
+  configure_connection(&conn)?;
+  build_db(&conn)?;
+
+  Ok(conn)
+}
+
+/// Stable name of the file behind `conn`, used to remember when two
+/// databases last synced.
+fn peer_name(conn: &Connection) -> String {
+  let path = conn.path().unwrap_or_default();
+  Path::new(path).canonicalize()
+    .map(|path| path.to_string_lossy().to_string())
+    .unwrap_or_else(|_| path.to_string())
+}
+
+fn last_synced(conn: &Connection, peer: &str) -> Result<Option<String>> {
+  conn.query_row("SELECT synced_at FROM sync_peers WHERE peer = ?", [peer], |row| row.get(0)).optional()
+}
+
+fn record_sync(conn: &Connection, peer: &str) -> Result<()> {
+  conn.execute(
+    &format!("INSERT INTO sync_peers (peer, synced_at) VALUES (?, {UPDATED_AT_NOW})
+      ON CONFLICT (peer) DO UPDATE SET synced_at = excluded.synced_at"),
+    [peer],
+  )?;
+
+  Ok(())
+}
+
+/// Every task, deleted ones included, keyed by UUID.
+pub fn read_rows(conn: &Connection) -> Result<BTreeMap<String, SyncRow>> {
+  let mut statement = conn.prepare(
+    "SELECT uuid, name, created_at, is_done, completed_at, completed_by, remind_at, reminded_at, deleted_at,
+      updated_at, COALESCE(name_updated_at, created_at), COALESCE(status_updated_at, created_at),
+      COALESCE(remind_updated_at, created_at), COALESCE(deleted_updated_at, deleted_at, created_at)
+      FROM tasks",
+  )?;
+  let rows_iter = statement.query_map([], |row| {
+    Ok(SyncRow {
+      uuid: row.get(0)?,
+      name: row.get(1)?,
+      created_at: row.get(2)?,
+      is_done: row.get(3)?,
+      completed_at: row.get(4)?,
+      completed_by: row.get(5)?,
+      remind_at: row.get(6)?,
+      reminded_at: row.get(7)?,
+      deleted_at: row.get(8)?,
+      updated_at: row.get(9)?,
+      name_updated_at: row.get(10)?,
+      status_updated_at: row.get(11)?,
+      remind_updated_at: row.get(12)?,
+      deleted_updated_at: row.get(13)?,
+    })
+  })?;
+
+  let mut rows = BTreeMap::new();
+  for row in rows_iter {
+    let row = row?;
+    rows.insert(row.uuid.clone(), row);
+  }
+
+  Ok(rows)
+}
+
+/// Insert a task copied from another database, keeping its UUID and clocks.
+pub fn insert_row(conn: &Connection, row: &SyncRow) -> Result<()> {
+  conn.execute(
+    "INSERT INTO tasks (uuid, name, created_at, is_done, completed_at, completed_by, remind_at, reminded_at,
+      deleted_at, updated_at, name_updated_at, status_updated_at, remind_updated_at, deleted_updated_at)
+      VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    [&row.uuid as &dyn ToSql, &row.name, &row.created_at, &row.is_done, &row.completed_at, &row.completed_by,
+     &row.remind_at, &row.reminded_at, &row.deleted_at, &row.updated_at, &row.name_updated_at,
+     &row.status_updated_at, &row.remind_updated_at, &row.deleted_updated_at],
+  )?;
+
+  Ok(())
+}
+
+fn update_row(conn: &Connection, row: &SyncRow) -> Result<()> {
+  conn.execute(
+    "UPDATE tasks SET name = ?, is_done = ?, completed_at = ?, completed_by = ?, remind_at = ?, reminded_at = ?,
+      deleted_at = ?, updated_at = ?, name_updated_at = ?, status_updated_at = ?, remind_updated_at = ?,
+      deleted_updated_at = ?
+      WHERE uuid = ?",
+    [&row.name as &dyn ToSql, &row.is_done, &row.completed_at, &row.completed_by, &row.remind_at,
+     &row.reminded_at, &row.deleted_at, &row.updated_at, &row.name_updated_at, &row.status_updated_at,
+     &row.remind_updated_at, &row.deleted_updated_at, &row.uuid],
+  )?;
+
+  Ok(())
+}
+
+fn show(value: &Option<String>) -> String {
+  value.clone().unwrap_or_else(|| "none".to_string())
+}
+
+/// Decide one field: the side whose clock is newer wins, the local one on a
+/// tie. Differences both sides made after `since` are also reported.
+fn other_wins(field: &'static str, local: (&str, String), other: (&str, String), since: Option<&str>,
+              row: &SyncRow, conflicts: &mut Vec<Conflict>) -> bool {
+  let ((local_clock, local_value), (other_clock, other_value)) = (local, other);
+  if local_value == other_value {
+    return false;
+  }
+
+  let winner = if other_clock > local_clock { Side::Other } else { Side::Local };
+  let changed = |clock: &str| since.is_none_or(|since| clock > since);
+  if changed(local_clock) && changed(other_clock) {
+    conflicts.push(Conflict {
+      uuid: row.uuid.clone(),
+      name: row.name.clone(),
+      field,
+      local: local_value,
+      other: other_value,
+      winner,
+    });
+  }
+
+  winner == Side::Other
+}
+
+/// Merge the two versions of a task field by field.
+pub fn merge_rows(local: &SyncRow, other: &SyncRow, since: Option<&str>, conflicts: &mut Vec<Conflict>) -> SyncRow {
+  let mut merged = local.clone();
+
+  if other_wins("name", (&local.name_updated_at, local.name.clone()), (&other.name_updated_at, other.name.clone()),
+                since, local, conflicts) {
+    merged.name = other.name.clone();
+  }
+  if other_wins("is_done", (&local.status_updated_at, local.is_done.to_string()),
+                (&other.status_updated_at, other.is_done.to_string()), since, local, conflicts)
+    || (local.is_done == other.is_done && other.status_updated_at > local.status_updated_at) {
+    merged.is_done = other.is_done;
+    merged.completed_at = other.completed_at.clone();
+    merged.completed_by = other.completed_by.clone();
+  }
+  if other_wins("remind_at", (&local.remind_updated_at, show(&local.remind_at)),
+                (&other.remind_updated_at, show(&other.remind_at)), since, local, conflicts)
+    || (local.remind_at == other.remind_at && other.remind_updated_at > local.remind_updated_at) {
+    merged.remind_at = other.remind_at.clone();
+    merged.reminded_at = other.reminded_at.clone();
+  }
+
+  // Deleting and restoring have their own clock, deleted on both sides keeps the first deletion
+  merged.deleted_at = match (&local.deleted_at, &other.deleted_at) {
+    (Some(local), Some(other)) => Some(local.clone().min(other.clone())),
+    (local_deleted, other_deleted) => {
+      let deleted = |deleted_at: &Option<String>| deleted_at.is_some().to_string();
+      if other_wins("deleted", (&local.deleted_updated_at, deleted(local_deleted)),
+                    (&other.deleted_updated_at, deleted(other_deleted)), since, local, conflicts) {
+        other_deleted.clone()
+      } else {
+        local_deleted.clone()
+      }
+    },
+  };
+  merged.updated_at = local.updated_at.clone().max(other.updated_at.clone());
+  merged.name_updated_at = local.name_updated_at.clone().max(other.name_updated_at.clone());
+  merged.status_updated_at = local.status_updated_at.clone().max(other.status_updated_at.clone());
+  merged.remind_updated_at = local.remind_updated_at.clone().max(other.remind_updated_at.clone());
+  merged.deleted_updated_at = local.deleted_updated_at.clone().max(other.deleted_updated_at.clone());
+
+  merged
+}
+
+/// What applying `merged` over `current` changes, if anything visible.
+fn describe_change(current: &SyncRow, merged: &SyncRow) -> Option<SyncChange> {
+  let kind = if current.deleted_at.is_none() && merged.deleted_at.is_some() {
+    ChangeKind::Deleted
+  } else if current.deleted_at.is_some() && merged.deleted_at.is_none() {
+    ChangeKind::Restored
+  } else {
+    let mut fields = Vec::new();
+    if current.name != merged.name {
+      fields.push(format!("name = {}", merged.name));
+    }
+    if current.is_done != merged.is_done {
+      fields.push(format!("is_done = {}", merged.is_done));
+    }
+    if current.remind_at != merged.remind_at {
+      fields.push(format!("remind_at = {}", show(&merged.remind_at)));
+    }
+    if fields.is_empty() {
+      return None;
+    }
+    ChangeKind::Updated(fields)
+  };
+
+  Some(SyncChange { uuid: merged.uuid.clone(), name: merged.name.clone(), kind })
+}
+
+/// Merge the tasks of `local` and `other` both ways, matching them by UUID:
+/// tasks missing on one side are copied over, and each field, deletion
+/// included, keeps the most recent change. With `dry_run` nothing is written
+/// and the report only tells what would change.
+///
+/// Only the tasks themselves are replicated: a copied task gets the next
+/// local id of its new database, and time entries and task owners stay in
+/// the database they were recorded in.
+pub fn sync_databases(local: &Connection, other: &Connection, dry_run: bool) -> Result<SyncReport> {
+  let (local_name, other_name) = (peer_name(local), peer_name(other));
+  if !local_name.is_empty() && local_name == other_name {
+    return Err(Error::InvalidParameterName("Cannot sync a database with itself".to_string()));
+  }
+
+  sync_with_peer(local, other, &other_name, dry_run)
+}
+
+/// Same as `sync_databases`, remembering the last sync under `other_name`
+/// instead of the file of `other`, for peers that are not a database file.
+/// Both databases are locked for writing before anything is read, so a
+/// concurrent command cannot change a row between its merge and its write.
+pub fn sync_with_peer(local: &Connection, other: &Connection, other_name: &str, dry_run: bool) -> Result<SyncReport> {
+  let local_name = peer_name(local);
+  with_transaction(local, |local| {
+    with_transaction(other, |other| merge_peers(local, other, &local_name, other_name, dry_run))
+  })
+}
+
+/// Merge the rows of `local` and `other` both ways, within their transactions.
+fn merge_peers(local: &Connection, other: &Connection, local_name: &str, other_name: &str, dry_run: bool) -> Result<SyncReport> {
+  let since = last_synced(local, other_name)?;
+  let (local_rows, other_rows) = (read_rows(local)?, read_rows(other)?);
+  let mut report = SyncReport::default();
+
+  for (uuid, local_row) in &local_rows {
+    match other_rows.get(uuid) {
+      Some(other_row) => {
+        let merged = merge_rows(local_row, other_row, since.as_deref(), &mut report.conflicts);
+        if merged != *local_row {
+          if !dry_run {
+            update_row(local, &merged)?;
+          }
+          report.into_local.extend(describe_change(local_row, &merged));
+        }
+        if merged != *other_row {
+          if !dry_run {
+            update_row(other, &merged)?;
+          }
+          report.into_other.extend(describe_change(other_row, &merged));
+        }
+      },
+      None => {
+        if !dry_run {
+          insert_row(other, local_row)?;
+        }
+        report.into_other.push(SyncChange { uuid: uuid.clone(), name: local_row.name.clone(), kind: ChangeKind::Added });
+      },
+    }
+  }
+
+  for (uuid, other_row) in other_rows.iter().filter(|(uuid, _)| !local_rows.contains_key(*uuid)) {
+    if !dry_run {
+      insert_row(local, other_row)?;
+    }
+    report.into_local.push(SyncChange { uuid: uuid.clone(), name: other_row.name.clone(), kind: ChangeKind::Added });
+  }
+
+  if !dry_run {
+    record_sync(local, other_name)?;
+    record_sync(other, local_name)?;
+  }
+
+  Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::task::{delete_task, insert_task, read_tasks, restore_task, update_task_status};
+  use std::fs;
+  use std::path::PathBuf;
+
+  struct TempDb {
+    path: PathBuf,
+    conn: Connection,
+  }
+
+  impl Drop for TempDb {
+    fn drop(&mut self) {
+      let _ = fs::remove_file(&self.path);
+    }
+  }
+
+  fn setup(name: &str) -> TempDb {
+    // Set up a database file of its own for each side of each test
+    let path = std::env::temp_dir().join(format!("rust-to-do-list-{}-sync-{name}.db", std::process::id()));
+    let _ = fs::remove_file(&path);
+    let conn = Connection::open(&path).unwrap();
+    // No need to wait for the disk in throwaway files
+    conn.pragma_update(None, "synchronous", "OFF").unwrap();
+    build_db(&conn).unwrap();
+    TempDb { path, conn }
+  }
+
+  fn names(conn: &Connection) -> Vec<String> {
+    read_tasks(conn).unwrap().into_iter().map(|task| task.name).collect()
+  }
+
+  fn set_clock(conn: &Connection, name: &str, column: &str, value: &str) {
+    conn.execute(&format!("UPDATE tasks SET {column} = ?, updated_at = ? WHERE name = ?"), [value, value, name]).unwrap();
+  }
+
+  #[test]
+  fn test_sync_copies_new_tasks_both_ways() {
+    let (local, other) = (setup("copy-local"), setup("copy-other"));
+    insert_task(&local.conn, "Local task".to_string()).unwrap();
+    insert_task(&other.conn, "Other task".to_string()).unwrap();
+
+    let report = sync_databases(&local.conn, &other.conn, false).unwrap();
+
+    assert_eq!((report.into_local.len(), report.into_other.len()), (1, 1));
+    assert_eq!(names(&local.conn), vec!["Local task", "Other task"]);
+    assert_eq!(names(&other.conn), vec!["Other task", "Local task"]);
+    assert_eq!(read_tasks(&local.conn).unwrap()[1].uuid, read_tasks(&other.conn).unwrap()[0].uuid);
+
+    // A second sync has nothing left to do
+    let report = sync_databases(&local.conn, &other.conn, false).unwrap();
+    assert!(report.into_local.is_empty() && report.into_other.is_empty() && report.conflicts.is_empty());
+  }
+
+  #[test]
+  fn test_sync_propagates_tombstones() {
+    let (local, other) = (setup("tombstone-local"), setup("tombstone-other"));
+    insert_task(&local.conn, "Shared".to_string()).unwrap();
+    sync_databases(&local.conn, &other.conn, false).unwrap();
+
+    let id = read_tasks(&other.conn).unwrap()[0].id;
+    delete_task(&other.conn, id).unwrap();
+    let report = sync_databases(&local.conn, &other.conn, false).unwrap();
+
+    assert_eq!(report.into_local[0].kind, ChangeKind::Deleted);
+    assert!(read_tasks(&local.conn).unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_sync_keeps_a_later_restore() {
+    let (local, other) = (setup("restore-local"), setup("restore-other"));
+    insert_task(&local.conn, "Shared".to_string()).unwrap();
+    let id = read_tasks(&local.conn).unwrap()[0].id;
+    delete_task(&local.conn, id).unwrap();
+    set_clock(&local.conn, "Shared", "deleted_updated_at", "2000-01-01 00:00:00.000");
+    sync_databases(&local.conn, &other.conn, false).unwrap();
+
+    restore_task(&local.conn, id).unwrap();
+    let report = sync_databases(&local.conn, &other.conn, false).unwrap();
+
+    assert_eq!(report.into_other[0].kind, ChangeKind::Restored);
+    assert_eq!((names(&local.conn), names(&other.conn)), (vec!["Shared".to_string()], vec!["Shared".to_string()]));
+    // Deleting again afterwards wins in turn
+    delete_task(&other.conn, read_tasks(&other.conn).unwrap()[0].id).unwrap();
+    set_clock(&other.conn, "Shared", "deleted_updated_at", "2999-01-01 00:00:00.000");
+    sync_databases(&local.conn, &other.conn, false).unwrap();
+    assert!(read_tasks(&local.conn).unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_sync_keeps_latest_field_and_reports_conflicts() {
+    let (local, other) = (setup("conflict-local"), setup("conflict-other"));
+    insert_task(&local.conn, "Shared".to_string()).unwrap();
+    sync_databases(&local.conn, &other.conn, false).unwrap();
+
+    // Both sides change the status after the sync, the other one last
+    let id = read_tasks(&local.conn).unwrap()[0].id;
+    update_task_status(&local.conn, id, true).unwrap();
+    update_task_status(&other.conn, id, true).unwrap();
+    update_task_status(&other.conn, id, false).unwrap();
+    set_clock(&local.conn, "Shared", "status_updated_at", "2999-01-01 00:00:00.000");
+    set_clock(&other.conn, "Shared", "status_updated_at", "2999-01-02 00:00:00.000");
+
+    let report = sync_databases(&local.conn, &other.conn, false).unwrap();
+
+    assert_eq!(report.conflicts.len(), 1);
+    assert_eq!((report.conflicts[0].field, report.conflicts[0].winner), ("is_done", Side::Other));
+    assert!(!read_tasks(&local.conn).unwrap()[0].is_done);
+    assert!(report.into_other.is_empty());
+  }
+
+  #[test]
+  fn test_sync_without_concurrent_change_is_not_a_conflict() {
+    let (local, other) = (setup("noconflict-local"), setup("noconflict-other"));
+    insert_task(&local.conn, "Shared".to_string()).unwrap();
+    sync_databases(&local.conn, &other.conn, false).unwrap();
+
+    let id = read_tasks(&other.conn).unwrap()[0].id;
+    update_task_status(&other.conn, id, true).unwrap();
+    let report = sync_databases(&local.conn, &other.conn, false).unwrap();
+
+    assert!(report.conflicts.is_empty());
+    assert_eq!(report.into_local[0].kind, ChangeKind::Updated(vec!["is_done = true".to_string()]));
+    assert!(read_tasks(&local.conn).unwrap()[0].is_done);
+  }
+
+  #[test]
+  fn test_sync_status_change_keeps_a_rename() {
+    let (local, other) = (setup("rename-local"), setup("rename-other"));
+    insert_task(&local.conn, "Shared".to_string()).unwrap();
+    sync_databases(&local.conn, &other.conn, false).unwrap();
+
+    // The local side renames the task, the other one completes it afterwards
+    set_clock(&local.conn, "Shared", "name_updated_at", "2999-01-01 00:00:00.000");
+    local.conn.execute("UPDATE tasks SET name = 'Renamed'", []).unwrap();
+    let id = read_tasks(&other.conn).unwrap()[0].id;
+    update_task_status(&other.conn, id, true).unwrap();
+    set_clock(&other.conn, "Shared", "status_updated_at", "2999-01-02 00:00:00.000");
+
+    let report = sync_databases(&local.conn, &other.conn, false).unwrap();
+
+    assert!(report.conflicts.is_empty());
+    for conn in [&local.conn, &other.conn] {
+      let task = &read_tasks(conn).unwrap()[0];
+      assert_eq!((task.name.as_str(), task.is_done), ("Renamed", true));
+    }
+  }
+
+  #[test]
+  fn test_sync_dry_run_writes_nothing() {
+    let (local, other) = (setup("dry-local"), setup("dry-other"));
+    insert_task(&local.conn, "Local task".to_string()).unwrap();
+
+    let report = sync_databases(&local.conn, &other.conn, true).unwrap();
+
+    assert_eq!(report.into_other.len(), 1);
+    assert!(read_tasks(&other.conn).unwrap().is_empty());
+    assert!(last_synced(&local.conn, &peer_name(&other.conn)).unwrap().is_none());
+  }
+
+  #[test]
+  fn test_sync_with_itself_or_missing_file_fails() {
+    let local = setup("self");
+    let same = Connection::open(&local.path).unwrap();
+
+    assert!(sync_databases(&local.conn, &same, false).is_err());
+    assert!(open_other_db("/nonexistent/rust-to-do-list.db").is_err());
+  }
+}
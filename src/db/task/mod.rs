@@ -1,20 +1,101 @@
-use rusqlite::{Connection, Error, Result};
-
+use std::fmt;
+
+use chrono::{NaiveDateTime, Utc};
+use rusqlite::{Connection, Error, Result, Row};
+use super::{add_column_if_missing, with_transaction};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Columns selected for every `Task`, in the order `task_from_row` reads them.
+/// The last one is the display index: the position of the task among the
+/// open ones, so it stays compact (1..N) however large the ids grow.
+pub const TASK_COLUMNS: &str = "id, name, is_done, created_at, completed_at, completed_by, uuid, updated_at, deleted_at,
+  CASE WHEN is_done = 0 AND deleted_at IS NULL THEN (
+    SELECT COUNT(*) FROM tasks AS open
+      WHERE open.is_done = 0 AND open.deleted_at IS NULL AND open.id <= tasks.id
+  ) END";
+
+/// SQL expression for the current UTC time with milliseconds, used for
+/// `updated_at` so close edits on two machines still compare correctly.
+pub const UPDATED_AT_NOW: &str = "strftime('%Y-%m-%d %H:%M:%f', 'now')";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Task {
   pub id: u32,
   pub name: String,
   pub is_done: bool,
   pub created_at: String,
+  pub completed_at: Option<String>,
+  pub completed_by: Option<String>,
+  /// Permanent identifier, stable across databases.
+  pub uuid: String,
+  /// Last time any field of the task changed, bumped by every mutation.
+  pub updated_at: String,
+  pub deleted_at: Option<String>,
+  /// Position among the open tasks, `None` once done or deleted.
+  pub index: Option<u32>,
+}
+
+impl fmt::Display for Task {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "id = {}, name = {}, is_done = {}, created_at = {}",
+           self.id, self.name, self.is_done, self.created_at)?;
+    if let Some(index) = self.index {
+      write!(f, ", index = @{}", index)?;
+    }
+    if let Some(completed_at) = &self.completed_at {
+      write!(f, ", completed_at = {} (done {})", completed_at, relative_time(completed_at))?;
+    }
+    if let Some(completed_by) = &self.completed_by {
+      write!(f, ", completed_by = {}", completed_by)?;
+    }
+    if let Some(deleted_at) = &self.deleted_at {
+      write!(f, ", deleted_at = {}", deleted_at)?;
+    }
+    write!(f, ", uuid = {}", self.uuid)
+  }
+}
+
+/// Build a `Task` from a row selected with `TASK_COLUMNS`.
+pub fn task_from_row(row: &Row) -> Result<Task> {
+  Ok(Task {
+    id: row.get(0)?,
+    name: row.get(1)?,
+    is_done: row.get(2)?,
+    created_at: row.get(3)?,
+    completed_at: row.get(4)?,
+    completed_by: row.get(5)?,
+    uuid: row.get(6)?,
+    updated_at: row.get(7)?,
+    deleted_at: row.get(8)?,
+    index: row.get(9)?,
+  })
 }
 
-impl Task {
-  pub fn log(&self){
-    println!("id = {}, name = {}, is_done = {}, created_at = {}",
-             self.id, self.name, self.is_done, self.created_at);
+/// Describe a stored UTC timestamp relative to now, e.g. `2 days ago`.
+pub fn relative_time(timestamp: &str) -> String {
+  match NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S") {
+    Ok(then) => relative_time_from(then, Utc::now().naive_utc()),
+    Err(_) => timestamp.to_string(),
   }
 }
 
+fn relative_time_from(then: NaiveDateTime, now: NaiveDateTime) -> String {
+  let seconds = (now - then).num_seconds().max(0);
+  let (amount, unit) = match seconds {
+    0..=59 => return "just now".to_string(),
+    60..=3599 => (seconds / 60, "minute"),
+    3600..=86399 => (seconds / 3600, "hour"),
+    _ => (seconds / 86400, "day"),
+  };
+  format!("{amount} {unit}{} ago", if amount == 1 { "" } else { "s" })
+}
+
 pub fn build_tasks_db_table (conn: &Connection) -> Result<()> {
+  with_transaction(conn, migrate_tasks_table)
+}
+
+fn migrate_tasks_table(conn: &Connection) -> Result<()> {
   conn.execute(
     "CREATE TABLE IF NOT EXISTS tasks (
       id INTEGER PRIMARY KEY AUTOINCREMENT,
@@ -25,67 +106,93 @@ pub fn build_tasks_db_table (conn: &Connection) -> Result<()> {
     )",
     [],
   ).unwrap();
+  add_column_if_missing(conn, "tasks", "owner_id", "INTEGER NULL REFERENCES users(id)")?;
+  add_column_if_missing(conn, "tasks", "remind_at", "DATETIME NULL")?;
+  add_column_if_missing(conn, "tasks", "reminded_at", "DATETIME NULL")?;
+  add_column_if_missing(conn, "tasks", "completed_at", "DATETIME NULL")?;
+  add_column_if_missing(conn, "tasks", "completed_by", "TEXT NULL")?;
+  add_column_if_missing(conn, "tasks", "uuid", "TEXT NULL")?;
+  backfill_uuids(conn)?;
+  conn.execute("CREATE UNIQUE INDEX IF NOT EXISTS tasks_uuid ON tasks (uuid)", [])?;
+  add_column_if_missing(conn, "tasks", "updated_at", "DATETIME NULL")?;
+  // Older rows last changed at their latest known event
+  conn.execute(
+    "UPDATE tasks SET updated_at = MAX(created_at, COALESCE(completed_at, ''), COALESCE(deleted_at, ''))
+      WHERE updated_at IS NULL",
+    [],
+  )?;
+  conn.execute("CREATE INDEX IF NOT EXISTS tasks_updated_at ON tasks (updated_at)", [])?;
+  // Per field clocks for sync, NULL until the field first changes
+  add_column_if_missing(conn, "tasks", "status_updated_at", "DATETIME NULL")?;
+  add_column_if_missing(conn, "tasks", "remind_updated_at", "DATETIME NULL")?;
+  add_column_if_missing(conn, "tasks", "name_updated_at", "DATETIME NULL")?;
+  add_column_if_missing(conn, "tasks", "deleted_updated_at", "DATETIME NULL")?;
+
+  Ok(())
+}
+
+/// Give a UUID to the tasks created before the column existed.
+fn backfill_uuids(conn: &Connection) -> Result<()> {
+  let mut statement = conn.prepare("SELECT id FROM tasks WHERE uuid IS NULL")?;
+  let ids = statement.query_map([], |row| row.get::<_, u32>(0))?.collect::<Result<Vec<_>>>()?;
+
+  for id in ids {
+    conn.execute("UPDATE tasks SET uuid = ? WHERE id = ?", [&Uuid::new_v4().to_string() as &dyn rusqlite::ToSql, &id])?;
+  }
 
   Ok(())
 }
 pub fn insert_task(conn: &Connection, task_name: String) -> Result<Task> {
-  if task_name == "" || task_name.is_empty() {
-    println!("Task name cannot be empty");
+  insert_task_with_owner(conn, task_name, None)
+}
+
+/// Insert a task belonging to the user `owner_id`.
+pub fn insert_owned_task(conn: &Connection, task_name: String, owner_id: u32) -> Result<Task> {
+  insert_task_with_owner(conn, task_name, Some(owner_id))
+}
+
+fn insert_task_with_owner(conn: &Connection, task_name: String, owner_id: Option<u32>) -> Result<Task> {
+  if task_name.is_empty() {
     return Err(Error::InvalidParameterName("Task name cannot be empty".to_string()));
   }
-  let sql = "INSERT INTO tasks (name, is_done) VALUES (?, ?) RETURNING id, name, is_done, created_at";
-  let mut statement = conn.prepare(sql)?;
-
-  let inserted_task = statement.query_row(
-      [&task_name as &dyn rusqlite::ToSql, &false],
-      |row| {
-          Ok(Task {
-              id: row.get(0)?,
-              name: row.get(1)?,
-              is_done: row.get(2)?,
-              created_at: row.get(3)?,
-          })
-      },
-  )?;
+  let sql = format!("INSERT INTO tasks (name, is_done, owner_id, uuid, updated_at) VALUES (?, ?, ?, ?, {UPDATED_AT_NOW})
+    RETURNING {TASK_COLUMNS}");
 
-  Ok(inserted_task)
+  conn.query_row(&sql, [&task_name as &dyn rusqlite::ToSql, &false, &owner_id, &Uuid::new_v4().to_string()], task_from_row)
 }
 
 pub fn update_task_status(conn: &Connection, id: u32, is_done: bool) -> Result<Task> {
-  let sql = "UPDATE tasks SET is_done = ? WHERE id = ?";
-  conn.execute(sql, &[&is_done, &id as &dyn rusqlite::ToSql]).unwrap();
-
-  let sql = "SELECT id, name, is_done, created_at FROM tasks WHERE id =?";
-  let mut statement = conn.prepare(sql)?;
-
-  let updated_task = statement.query_row(
-    &[&id],
-    |row| {
-      Ok(Task {
-        id: row.get(0)?,
-        name: row.get(1)?,
-        is_done: row.get(2)?,
-        created_at: row.get(3)?,
-      })
-    },
-  )?;
-  Ok(updated_task)
+  update_task_status_by(conn, id, is_done, None)
+}
+
+/// Same as `update_task_status`, also recording who completed the task.
+pub fn update_task_status_by(conn: &Connection, id: u32, is_done: bool, completed_by: Option<&str>) -> Result<Task> {
+  // Keep the first completion when a done task is marked done again
+  let sql = format!("UPDATE tasks SET is_done = ?1,
+    completed_at = CASE WHEN ?1 THEN COALESCE(completed_at, CURRENT_TIMESTAMP) ELSE NULL END,
+    completed_by = CASE WHEN ?1 THEN CASE WHEN is_done THEN completed_by ELSE ?3 END ELSE NULL END,
+    updated_at = {UPDATED_AT_NOW}, status_updated_at = {UPDATED_AT_NOW}
+    WHERE id = ?2 AND deleted_at IS NULL
+    RETURNING {TASK_COLUMNS}");
+
+  conn.query_row(&sql, [&is_done, &id as &dyn rusqlite::ToSql, &completed_by], task_from_row)
+}
+
+/// Set the status of every task in `ids` in a single transaction, leaving
+/// all of them untouched if any is missing.
+pub fn update_tasks_status(conn: &Connection, ids: &[u32], is_done: bool, completed_by: Option<&str>) -> Result<Vec<Task>> {
+  with_transaction(conn, |conn| {
+    ids.iter().map(|id| update_task_status_by(conn, *id, is_done, completed_by)).collect()
+  })
 }
 
 pub fn read_tasks(conn: &Connection) -> Result<Vec<Task>> {
-  let sql = format!("SELECT id, name, is_done, created_at FROM tasks WHERE deleted_at is NULL");
+  let sql = format!("SELECT {TASK_COLUMNS} FROM tasks WHERE deleted_at is NULL");
   
   let mut statement = conn.prepare(&sql).unwrap();
 
   let tasks_iter = statement
-    .query_map([], |row| {
-        Ok(Task {
-            id: row.get(0)?, 
-            name: row.get(1)?,
-            is_done: row.get(2)?,
-            created_at: row.get(3)?,
-        })
-    })
+    .query_map([], task_from_row)
     .unwrap();
 
   let mut tasks = Vec::new();
@@ -96,55 +203,140 @@ pub fn read_tasks(conn: &Connection) -> Result<Vec<Task>> {
   Ok(tasks)
 }
 
+/// The task `id`, deleted or not.
+pub fn read_task(conn: &Connection, id: u32) -> Result<Task> {
+  let sql = format!("SELECT {TASK_COLUMNS} FROM tasks WHERE id = ?");
+  conn.query_row(&sql, [&id], task_from_row)
+}
+
+/// Every task, deleted ones included, oldest first.
+pub fn read_all_tasks(conn: &Connection) -> Result<Vec<Task>> {
+  let sql = format!("SELECT {TASK_COLUMNS} FROM tasks ORDER BY id");
+  let mut statement = conn.prepare(&sql)?;
+
+  let tasks_iter = statement.query_map([], task_from_row)?;
+
+  let mut tasks = Vec::new();
+  for task in tasks_iter {
+    tasks.push(task?);
+  }
+
+  Ok(tasks)
+}
+
+/// Live tasks completed at or after `since` (a `YYYY-MM-DD` date or a
+/// full UTC timestamp), oldest completion first.
+pub fn read_tasks_completed_since(conn: &Connection, since: &str) -> Result<Vec<Task>> {
+  let sql = format!("SELECT {TASK_COLUMNS} FROM tasks
+    WHERE deleted_at IS NULL AND is_done = 1 AND completed_at >= ?
+    ORDER BY completed_at, id");
+  let mut statement = conn.prepare(&sql)?;
+
+  let tasks_iter = statement.query_map([since], task_from_row)?;
+
+  let mut tasks = Vec::new();
+  for task in tasks_iter {
+    tasks.push(task?);
+  }
+
+  Ok(tasks)
+}
+
+/// Every task, deleted ones included, changed after `since` (a UTC
+/// timestamp as stored in `updated_at`), oldest change first. Deleted tasks
+/// are returned with their `deleted_at` so replicas can apply tombstones.
+pub fn changed_since(conn: &Connection, since: &str) -> Result<Vec<Task>> {
+  let sql = format!("SELECT {TASK_COLUMNS} FROM tasks WHERE updated_at > ? ORDER BY updated_at, id");
+  let mut statement = conn.prepare(&sql)?;
+
+  let tasks_iter = statement.query_map([since], task_from_row)?;
+
+  let mut tasks = Vec::new();
+  for task in tasks_iter {
+    tasks.push(task?);
+  }
+
+  Ok(tasks)
+}
+
+/// Same as `read_tasks`, restricted to the tasks of the user `owner_id`.
+pub fn read_owned_tasks(conn: &Connection, owner_id: u32) -> Result<Vec<Task>> {
+  let sql = format!("SELECT {TASK_COLUMNS} FROM tasks WHERE deleted_at IS NULL AND owner_id = ?");
+  let mut statement = conn.prepare(&sql)?;
+
+  let tasks_iter = statement.query_map([&owner_id], task_from_row)?;
+
+  let mut tasks = Vec::new();
+  for task in tasks_iter {
+    tasks.push(task?);
+  }
+
+  Ok(tasks)
+}
+
+/// The live task `id` of the user `owner_id`, failing with
+/// `QueryReturnedNoRows` for the tasks of other users as for missing ones.
+pub fn read_owned_task(conn: &Connection, id: u32, owner_id: u32) -> Result<Task> {
+  let sql = format!("SELECT {TASK_COLUMNS} FROM tasks WHERE id = ? AND owner_id = ? AND deleted_at IS NULL");
+  conn.query_row(&sql, [&id, &owner_id], task_from_row)
+}
+
+/// Check that the live task `id` belongs to `owner_id`, failing with
+/// `QueryReturnedNoRows` otherwise so foreign tasks look like missing ones.
+pub fn ensure_task_owner(conn: &Connection, id: u32, owner_id: u32) -> Result<()> {
+  let sql = "SELECT id FROM tasks WHERE id = ? AND owner_id = ? AND deleted_at IS NULL";
+  conn.query_row(sql, [&id, &owner_id], |row| row.get::<_, u32>(0))?;
+
+  Ok(())
+}
+
 pub fn delete_task(conn: &Connection, id: u32) -> Result<Task> {
-  // Find task
-  let sql = "SELECT id, name, is_done, created_at FROM tasks WHERE id =? AND deleted_at IS NULL";
-  let mut statement = conn.prepare(sql)?;
-
-  let task = statement.query_row(
-    &[&id],
-    |row| {
-      Ok(Task {
-        id: row.get(0)?,
-        name: row.get(1)?,
-        is_done: row.get(2)?,
-        created_at: row.get(3)?,
-      })
-    },
-  );
-
-  match task {
-    Ok(task) => {
-      task.log();
-    },
-    Err(_) => {
-      println!("Task not found");
-      return Err(rusqlite::Error::QueryReturnedNoRows);
-    },
-  }
-
-  // Delete task
-  let sql = "UPDATE tasks SET deleted_at = CURRENT_TIMESTAMP WHERE id = ?";
-  conn.execute(sql, &[&id]).unwrap();
-  let sql = "SELECT id, name, is_done, created_at FROM tasks WHERE id = ? AND deleted_at IS NOT NULL";
-  let mut statement = conn.prepare(sql)?;
-
-  let deleted_task = statement.query_row(
-    &[&id],
-    |row| {
-      Ok(Task {
-        id: row.get(0)?,
-        name: row.get(1)?,
-        is_done: row.get(2)?,
-        created_at: row.get(3)?,
-      })
-    },
-  )?;
+  let sql = format!("UPDATE tasks SET deleted_at = CURRENT_TIMESTAMP, updated_at = {UPDATED_AT_NOW},
+    deleted_updated_at = {UPDATED_AT_NOW}
+    WHERE id = ? AND deleted_at IS NULL
+    RETURNING {TASK_COLUMNS}");
 
-  return Ok(deleted_task)
+  conn.query_row(&sql, [&id], task_from_row)
+}
+
+/// Delete every task in `ids` in a single transaction, leaving all of them
+/// untouched if any is missing.
+pub fn delete_tasks(conn: &Connection, ids: &[u32]) -> Result<Vec<Task>> {
+  with_transaction(conn, |conn| {
+    ids.iter().map(|id| delete_task(conn, *id)).collect()
+  })
+}
+
+/// Add `tasks` as they are, keeping their ids, UUIDs and timestamps, in a
+/// single transaction.
+pub fn import_tasks(conn: &Connection, tasks: &[Task]) -> Result<()> {
+  with_transaction(conn, |conn| {
+    for task in tasks {
+      conn.execute(
+        "INSERT INTO tasks (id, uuid, name, is_done, created_at, completed_at, completed_by, updated_at, deleted_at)
+          VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        [&task.id as &dyn rusqlite::ToSql, &task.uuid, &task.name, &task.is_done, &task.created_at,
+         &task.completed_at, &task.completed_by, &task.updated_at, &task.deleted_at],
+      )?;
+    }
+    Ok(())
+  })
+}
+
+/// Bring back the deleted task `id`, failing with `QueryReturnedNoRows`
+/// when there is no such deleted task. Syncing keeps the restore over any
+/// deletion made before it.
+pub fn restore_task(conn: &Connection, id: u32) -> Result<Task> {
+  let sql = format!("UPDATE tasks SET deleted_at = NULL, updated_at = {UPDATED_AT_NOW},
+    deleted_updated_at = {UPDATED_AT_NOW}
+    WHERE id = ? AND deleted_at IS NOT NULL
+    RETURNING {TASK_COLUMNS}");
+
+  conn.query_row(&sql, [&id], task_from_row)
 }
 
 #[cfg(test)]
+#[allow(clippy::needless_return)]
 mod tests {
   use super::*;
   use super::super::connect_db;
@@ -165,6 +357,12 @@ mod tests {
           name: "Test task".to_string(),
           is_done: false,
           created_at: "".to_string(),
+          completed_at: None,
+          completed_by: None,
+          uuid: "".to_string(),
+          updated_at: "".to_string(),
+          deleted_at: None,
+          index: None,
         };
 
         match insert_task(&conn, "Test task".to_string()) {
@@ -176,13 +374,13 @@ mod tests {
           }
           Err(err) => {
             eprintln!("Failed to insert task: {}", err);
-            assert!(false);
+            panic!();
           }
         }
       }
       Err(err) => {
         eprintln!("Error connecting to the database: {}", err);
-        assert!(false);
+        panic!();
       }
     }
   }
@@ -250,7 +448,7 @@ mod tests {
           }
           Err(err) => {
               eprintln!("Failed to insert task with a very long name: {}", err);
-              assert!(false, "Expected to successfully insert a task with a very long name.");
+              panic!("Expected to successfully insert a task with a very long name.");
           }
       }
   }
@@ -272,7 +470,7 @@ mod tests {
           }
           Err(err) => {
               eprintln!("Failed to insert task with special characters: {}", err);
-              assert!(false, "Expected to successfully insert a task with special characters in the name.");
+              panic!("Expected to successfully insert a task with special characters in the name.");
           }
       }
   }
@@ -288,11 +486,11 @@ mod tests {
       // Assertions
       match result {
           Ok(task) => {
-              assert_eq!(task.is_done, false, "The 'is_done' field should be set to false upon insertion.");
+              assert!(!task.is_done, "The 'is_done' field should be set to false upon insertion.");
           }
           Err(err) => {
               eprintln!("Failed to insert task: {}", err);
-              assert!(false, "Expected to successfully insert a task.");
+              panic!("Expected to successfully insert a task.");
           }
       }
   }
@@ -303,7 +501,7 @@ mod tests {
 
       let updated_task = update_task_status(&conn, task.id, true).unwrap();
 
-      assert_eq!(updated_task.is_done, true);
+      assert!(updated_task.is_done);
       assert_eq!(updated_task.id, task.id);
       assert_eq!(updated_task.name, task.name);
   }
@@ -324,7 +522,7 @@ mod tests {
       // Update to the same status
       let updated_task = update_task_status(&conn, task.id, false).unwrap();
 
-      assert_eq!(updated_task.is_done, false);
+      assert!(!updated_task.is_done);
       assert_eq!(updated_task.id, task.id);
       assert_eq!(updated_task.name, task.name);
   }
@@ -340,7 +538,7 @@ mod tests {
     // Update to the same status again
     let updated_task_again = update_task_status(&conn, task.id, false).unwrap();
 
-    assert_eq!(updated_task_again.is_done, false);
+    assert!(!updated_task_again.is_done);
     assert_eq!(updated_task_again.id, task.id);
     assert_eq!(updated_task_again.name, task.name);
   }
@@ -419,7 +617,7 @@ mod tests {
       let deleted_task = insert_task(&conn, "Deleted Task".to_string()).unwrap();
       conn.execute(
           "UPDATE tasks SET deleted_at = datetime('now') WHERE id = ?",
-          &[&deleted_task.id],
+          [&deleted_task.id],
       )
       .unwrap();
 
@@ -443,13 +641,13 @@ mod tests {
       let completed_task = insert_task(&conn, "Completed Task".to_string()).unwrap();
       conn.execute(
           "UPDATE tasks SET is_done = 1 WHERE id = ?",
-          &[&completed_task.id],
+          [&completed_task.id],
       )
       .unwrap();
       let deleted_task = insert_task(&conn, "Deleted Task".to_string()).unwrap();
       conn.execute(
           "UPDATE tasks SET deleted_at = datetime('now') WHERE id = ?",
-          &[&deleted_task.id],
+          [&deleted_task.id],
       )
       .unwrap();
 
@@ -539,4 +737,220 @@ mod tests {
     let deleted_at: Option<String> = statement.query_row([&task.id], |row| row.get(0)).unwrap();
     assert!(deleted_at.is_some(), "Expected task to be marked as deleted.");
   }
-}
\ No newline at end of file
+
+  #[test]
+  fn test_read_owned_tasks_only_returns_owner_tasks() {
+    let conn = setup().unwrap();
+    let (alice, _) = crate::db::user::insert_user(&conn, "alice".to_string()).unwrap();
+    let (bob, _) = crate::db::user::insert_user(&conn, "bob".to_string()).unwrap();
+
+    insert_owned_task(&conn, "Alice task".to_string(), alice.id).unwrap();
+    insert_owned_task(&conn, "Bob task".to_string(), bob.id).unwrap();
+    insert_task(&conn, "Local task".to_string()).unwrap();
+
+    let tasks = read_owned_tasks(&conn, alice.id).unwrap();
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0].name, "Alice task");
+    assert_eq!(read_tasks(&conn).unwrap().len(), 3);
+  }
+
+  #[test]
+  fn test_ensure_task_owner() {
+    let conn = setup().unwrap();
+    let (alice, _) = crate::db::user::insert_user(&conn, "alice".to_string()).unwrap();
+    let (bob, _) = crate::db::user::insert_user(&conn, "bob".to_string()).unwrap();
+    let task = insert_owned_task(&conn, "Alice task".to_string(), alice.id).unwrap();
+
+    assert!(ensure_task_owner(&conn, task.id, alice.id).is_ok());
+    assert!(matches!(ensure_task_owner(&conn, task.id, bob.id), Err(Error::QueryReturnedNoRows)));
+  }
+
+  #[test]
+  fn test_update_task_status_records_completion_time() {
+    let conn = setup().unwrap();
+    let task = insert_task(&conn, "Test task".to_string()).unwrap();
+
+    let completed_at = |conn: &Connection| -> Option<String> {
+      conn.query_row("SELECT completed_at FROM tasks WHERE id = ?", [&task.id], |row| row.get(0)).unwrap()
+    };
+
+    update_task_status(&conn, task.id, true).unwrap();
+    assert!(completed_at(&conn).is_some(), "Expected completed_at to be set when done");
+
+    conn.execute("UPDATE tasks SET completed_at = '2030-01-01 00:00:00' WHERE id = ?", [&task.id]).unwrap();
+    update_task_status(&conn, task.id, true).unwrap();
+    assert_eq!(completed_at(&conn).as_deref(), Some("2030-01-01 00:00:00"));
+
+    update_task_status(&conn, task.id, false).unwrap();
+    assert!(completed_at(&conn).is_none(), "Expected completed_at to be cleared when reopened");
+  }
+
+  #[test]
+  fn test_update_task_status_by_records_who_completed() {
+    let conn = setup().unwrap();
+    let task = insert_task(&conn, "Test task".to_string()).unwrap();
+
+    let updated = update_task_status_by(&conn, task.id, true, Some("alice")).unwrap();
+    assert_eq!(updated.completed_by.as_deref(), Some("alice"));
+    assert!(updated.completed_at.is_some());
+
+    // Marking an already done task again keeps the original completer
+    let updated = update_task_status_by(&conn, task.id, true, Some("bob")).unwrap();
+    assert_eq!(updated.completed_by.as_deref(), Some("alice"));
+
+    let reopened = update_task_status_by(&conn, task.id, false, Some("bob")).unwrap();
+    assert!(reopened.completed_at.is_none());
+    assert!(reopened.completed_by.is_none());
+  }
+
+  #[test]
+  fn test_read_tasks_completed_since() {
+    let conn = setup().unwrap();
+    let old = insert_task(&conn, "Old".to_string()).unwrap();
+    let recent = insert_task(&conn, "Recent".to_string()).unwrap();
+    insert_task(&conn, "Open".to_string()).unwrap();
+    update_task_status(&conn, old.id, true).unwrap();
+    update_task_status(&conn, recent.id, true).unwrap();
+    conn.execute("UPDATE tasks SET completed_at = '2030-01-01 10:00:00' WHERE id = ?", [&old.id]).unwrap();
+    conn.execute("UPDATE tasks SET completed_at = '2030-01-05 10:00:00' WHERE id = ?", [&recent.id]).unwrap();
+
+    let tasks = read_tasks_completed_since(&conn, "2030-01-03").unwrap();
+
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0].id, recent.id);
+  }
+
+  #[test]
+  fn test_relative_time() {
+    let now = NaiveDateTime::parse_from_str("2030-01-10 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap();
+    let ago = |seconds: i64| relative_time_from(now - chrono::Duration::seconds(seconds), now);
+
+    assert_eq!(ago(10), "just now");
+    assert_eq!(ago(60), "1 minute ago");
+    assert_eq!(ago(5 * 3600), "5 hours ago");
+    assert_eq!(ago(2 * 86400 + 100), "2 days ago");
+  }
+
+  #[test]
+  fn test_bulk_update_and_delete() {
+    let conn = setup().unwrap();
+    let first = insert_task(&conn, "First".to_string()).unwrap();
+    let second = insert_task(&conn, "Second".to_string()).unwrap();
+
+    let updated = update_tasks_status(&conn, &[first.id, second.id], true, None).unwrap();
+    assert!(updated.iter().all(|task| task.is_done));
+
+    delete_tasks(&conn, &[first.id, second.id]).unwrap();
+    assert!(read_tasks(&conn).unwrap().is_empty());
+  }
+
+  #[test]
+  fn test_bulk_operations_roll_back_on_missing_task() {
+    let conn = setup().unwrap();
+    let task = insert_task(&conn, "Task".to_string()).unwrap();
+
+    assert!(update_tasks_status(&conn, &[task.id, 999], true, None).is_err());
+    assert!(!read_tasks(&conn).unwrap()[0].is_done);
+
+    assert!(delete_tasks(&conn, &[task.id, 999]).is_err());
+    assert_eq!(read_tasks(&conn).unwrap().len(), 1);
+  }
+
+  #[test]
+  fn test_display_index_is_compacted_among_open_tasks() {
+    let conn = setup().unwrap();
+    let first = insert_task(&conn, "First".to_string()).unwrap();
+    let second = insert_task(&conn, "Second".to_string()).unwrap();
+    let third = insert_task(&conn, "Third".to_string()).unwrap();
+    assert_eq!(third.index, Some(3));
+
+    update_task_status(&conn, first.id, true).unwrap();
+    delete_task(&conn, second.id).unwrap();
+
+    let tasks = read_tasks(&conn).unwrap();
+    assert_eq!(tasks.iter().map(|task| task.index).collect::<Vec<_>>(), vec![None, Some(1)]);
+    assert_ne!(tasks[0].uuid, tasks[1].uuid);
+  }
+
+  #[test]
+  fn test_mutations_bump_updated_at() {
+    let conn = setup().unwrap();
+    let task = insert_task(&conn, "Task".to_string()).unwrap();
+    let set_old = || conn.execute("UPDATE tasks SET updated_at = '2000-01-01 00:00:00.000'", []).unwrap();
+
+    set_old();
+    assert!(update_task_status(&conn, task.id, true).unwrap().updated_at.as_str() > "2000-01-01");
+
+    set_old();
+    assert!(delete_task(&conn, task.id).unwrap().updated_at.as_str() > "2000-01-01");
+  }
+
+  #[test]
+  fn test_changed_since_includes_tombstones() {
+    let conn = setup().unwrap();
+    let kept = insert_task(&conn, "Kept".to_string()).unwrap();
+    let deleted = insert_task(&conn, "Deleted".to_string()).unwrap();
+    let untouched = insert_task(&conn, "Untouched".to_string()).unwrap();
+    conn.execute("UPDATE tasks SET updated_at = '2000-01-01 00:00:00.000'", []).unwrap();
+
+    update_task_status(&conn, kept.id, true).unwrap();
+    delete_task(&conn, deleted.id).unwrap();
+
+    let changed = changed_since(&conn, "2000-01-01 00:00:00.000").unwrap();
+    let ids: Vec<u32> = changed.iter().map(|task| task.id).collect();
+    assert!(ids.contains(&kept.id) && ids.contains(&deleted.id) && !ids.contains(&untouched.id));
+    assert!(changed.iter().find(|task| task.id == deleted.id).unwrap().deleted_at.is_some());
+  }
+
+  #[test]
+  fn test_restore_deleted_task() {
+    let conn = setup().unwrap();
+    let task = insert_task(&conn, "Restored".to_string()).unwrap();
+    delete_task(&conn, task.id).unwrap();
+
+    let restored = restore_task(&conn, task.id).unwrap();
+
+    assert!(restored.deleted_at.is_none());
+    assert_eq!(read_tasks(&conn).unwrap().len(), 1);
+    assert!(matches!(restore_task(&conn, task.id), Err(Error::QueryReturnedNoRows)));
+    assert!(matches!(read_task(&conn, 999), Err(Error::QueryReturnedNoRows)));
+  }
+
+  #[test]
+  fn test_update_task_status_skips_deleted_task() {
+    let conn = setup().unwrap();
+    let task = insert_task(&conn, "Deleted".to_string()).unwrap();
+    delete_task(&conn, task.id).unwrap();
+
+    assert!(matches!(update_task_status(&conn, task.id, true), Err(Error::QueryReturnedNoRows)));
+    assert!(matches!(update_tasks_status(&conn, &[task.id], false, None), Err(Error::QueryReturnedNoRows)));
+    let stored: bool = conn.query_row("SELECT is_done FROM tasks WHERE id = ?", [task.id], |row| row.get(0)).unwrap();
+    assert!(!stored);
+  }
+
+  #[test]
+  fn test_operations_compose_in_one_transaction() {
+    let conn = setup().unwrap();
+
+    let result = with_transaction(&conn, |conn| {
+      let task = insert_task(conn, "Rolled back".to_string())?;
+      update_task_status(conn, task.id, true)?;
+      update_task_status(conn, 999, true)
+    });
+
+    assert!(matches!(result, Err(Error::QueryReturnedNoRows)));
+    assert!(read_tasks(&conn).unwrap().is_empty(), "The insert is undone with the failed update");
+  }
+
+  #[test]
+  fn test_returned_task_matches_stored_row() {
+    let conn = setup().unwrap();
+    insert_task(&conn, "First".to_string()).unwrap();
+    let second = insert_task(&conn, "Second".to_string()).unwrap();
+    assert_eq!(second.index, Some(2));
+
+    let deleted = delete_task(&conn, second.id).unwrap();
+    let stored = read_task(&conn, second.id).unwrap();
+    assert_eq!((deleted.deleted_at, deleted.updated_at, deleted.index), (stored.deleted_at, stored.updated_at, None));
+  }
+}
@@ -0,0 +1,266 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use rusqlite::{Connection, Error, OptionalExtension, Result};
+
+/// Format timer timestamps are stored in, in local time.
+pub const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+pub struct TimeEntry {
+  pub id: u32,
+  pub task_id: u32,
+  pub started_at: String,
+  pub stopped_at: Option<String>,
+}
+
+impl fmt::Display for TimeEntry {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "id = {}, task_id = {}, started_at = {}, stopped_at = {}",
+           self.id, self.task_id, self.started_at, self.stopped_at.as_deref().unwrap_or("running"))
+  }
+}
+
+/// Hours spent per task and per day since a given date.
+pub struct TimeReport {
+  pub per_task: Vec<(u32, String, Duration)>,
+  pub per_day: Vec<(NaiveDate, Duration)>,
+}
+
+pub fn build_time_entries_db_table (conn: &Connection) -> Result<()> {
+  conn.execute(
+    "CREATE TABLE IF NOT EXISTS time_entries (
+      id INTEGER PRIMARY KEY AUTOINCREMENT,
+      task_id INTEGER NOT NULL REFERENCES tasks(id),
+      started_at DATETIME NOT NULL,
+      stopped_at DATETIME NULL
+    )",
+    [],
+  )?;
+  // At most one row may have no stop time, i.e. a single running timer
+  conn.execute(
+    "CREATE UNIQUE INDEX IF NOT EXISTS time_entries_single_running
+      ON time_entries ((stopped_at IS NULL)) WHERE stopped_at IS NULL",
+    [],
+  )?;
+
+  Ok(())
+}
+
+fn parse_time(value: &str) -> Result<NaiveDateTime> {
+  NaiveDateTime::parse_from_str(value, TIME_FORMAT)
+    .map_err(|_| Error::InvalidParameterName(format!("Invalid stored time '{value}'")))
+}
+
+fn row_to_entry(row: &rusqlite::Row) -> Result<TimeEntry> {
+  Ok(TimeEntry {
+    id: row.get(0)?,
+    task_id: row.get(1)?,
+    started_at: row.get(2)?,
+    stopped_at: row.get(3)?,
+  })
+}
+
+/// The timer currently running, if any.
+pub fn running_timer(conn: &Connection) -> Result<Option<TimeEntry>> {
+  let sql = "SELECT id, task_id, started_at, stopped_at FROM time_entries WHERE stopped_at IS NULL";
+  conn.query_row(sql, [], row_to_entry).optional()
+}
+
+/// Start a timer on a live task. Fails while another timer is running.
+pub fn start_timer(conn: &Connection, task_id: u32, now: NaiveDateTime) -> Result<TimeEntry> {
+  if let Some(running) = running_timer(conn)? {
+    return Err(Error::InvalidParameterName(format!("A timer is already running for task {}", running.task_id)));
+  }
+  conn.query_row("SELECT id FROM tasks WHERE id = ? AND deleted_at IS NULL", [&task_id], |row| row.get::<_, u32>(0))?;
+
+  let sql = "INSERT INTO time_entries (task_id, started_at) VALUES (?, ?) RETURNING id, task_id, started_at, stopped_at";
+  let started_at = now.format(TIME_FORMAT).to_string();
+  conn.query_row(sql, [&task_id as &dyn rusqlite::ToSql, &started_at], row_to_entry)
+}
+
+/// Stop the running timer and return the finished entry.
+pub fn stop_timer(conn: &Connection, now: NaiveDateTime) -> Result<TimeEntry> {
+  let sql = "UPDATE time_entries SET stopped_at = ? WHERE stopped_at IS NULL RETURNING id, task_id, started_at, stopped_at";
+  let stopped_at = now.format(TIME_FORMAT).to_string();
+  conn.query_row(sql, [&stopped_at], row_to_entry)
+}
+
+fn read_entries(conn: &Connection, sql: &str, params: &[&dyn rusqlite::ToSql]) -> Result<Vec<(TimeEntry, String)>> {
+  let mut statement = conn.prepare(sql)?;
+  let entries_iter = statement.query_map(params, |row| Ok((row_to_entry(row)?, row.get(4)?)))?;
+
+  let mut entries = Vec::new();
+  for entry in entries_iter {
+    entries.push(entry?);
+  }
+
+  Ok(entries)
+}
+
+/// Start and end of an entry, a running one ending at `now`.
+fn entry_span(entry: &TimeEntry, now: NaiveDateTime) -> Result<(NaiveDateTime, NaiveDateTime)> {
+  let start = parse_time(&entry.started_at)?;
+  let end = match &entry.stopped_at {
+    Some(stopped_at) => parse_time(stopped_at)?,
+    None => now,
+  };
+  Ok((start, end.max(start)))
+}
+
+/// Total time tracked on a task, including a timer still running.
+pub fn task_duration(conn: &Connection, task_id: u32, now: NaiveDateTime) -> Result<Duration> {
+  let sql = "SELECT e.id, e.task_id, e.started_at, e.stopped_at, t.name
+    FROM time_entries e JOIN tasks t ON t.id = e.task_id WHERE e.task_id = ?";
+  let mut total = Duration::zero();
+  for (entry, _) in read_entries(conn, sql, &[&task_id])? {
+    let (start, end) = entry_span(&entry, now)?;
+    total += end - start;
+  }
+
+  Ok(total)
+}
+
+/// Sum the time tracked since `since`, per task and per calendar day.
+/// Entries crossing midnight are split between the days they cover.
+pub fn time_report(conn: &Connection, since: NaiveDateTime, now: NaiveDateTime) -> Result<TimeReport> {
+  let sql = "SELECT e.id, e.task_id, e.started_at, e.stopped_at, t.name
+    FROM time_entries e JOIN tasks t ON t.id = e.task_id
+    WHERE e.stopped_at IS NULL OR e.stopped_at > ?
+    ORDER BY e.started_at";
+  let since_text = since.format(TIME_FORMAT).to_string();
+
+  let mut per_task: BTreeMap<u32, (String, Duration)> = BTreeMap::new();
+  let mut per_day: BTreeMap<NaiveDate, Duration> = BTreeMap::new();
+
+  for (entry, name) in read_entries(conn, sql, &[&since_text])? {
+    let (start, end) = entry_span(&entry, now)?;
+    let mut cursor = start.max(since);
+    if cursor >= end {
+      continue;
+    }
+
+    let task_total = per_task.entry(entry.task_id).or_insert((name, Duration::zero()));
+    task_total.1 += end - cursor;
+
+    while cursor < end {
+      let next_midnight = (cursor.date() + Duration::days(1)).and_hms_opt(0, 0, 0).unwrap();
+      let slice_end = end.min(next_midnight);
+      *per_day.entry(cursor.date()).or_insert(Duration::zero()) += slice_end - cursor;
+      cursor = slice_end;
+    }
+  }
+
+  Ok(TimeReport {
+    per_task: per_task.into_iter().map(|(id, (name, duration))| (id, name, duration)).collect(),
+    per_day: per_day.into_iter().collect(),
+  })
+}
+
+/// Render a duration as `HH:MM:SS`.
+pub fn format_duration(duration: Duration) -> String {
+  let seconds = duration.num_seconds().max(0);
+  format!("{:02}:{:02}:{:02}", seconds / 3600, (seconds % 3600) / 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::connect_db;
+  use super::super::task::{delete_task, insert_task};
+
+  fn setup() -> Result<Connection>{
+    // Set up test environment
+    connect_db(Some(true), None)
+  }
+
+  fn at(value: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(value, TIME_FORMAT).unwrap()
+  }
+
+  #[test]
+  fn test_start_and_stop_timer() {
+    let conn = setup().unwrap();
+    let task = insert_task(&conn, "Billable".to_string()).unwrap();
+
+    start_timer(&conn, task.id, at("2030-01-01 09:00:00")).unwrap();
+    let entry = stop_timer(&conn, at("2030-01-01 10:30:00")).unwrap();
+
+    assert_eq!(entry.task_id, task.id);
+    assert_eq!(entry.stopped_at.as_deref(), Some("2030-01-01 10:30:00"));
+    assert_eq!(task_duration(&conn, task.id, at("2030-01-02 00:00:00")).unwrap(), Duration::minutes(90));
+  }
+
+  #[test]
+  fn test_only_one_timer_can_run() {
+    let conn = setup().unwrap();
+    let first = insert_task(&conn, "First".to_string()).unwrap();
+    let second = insert_task(&conn, "Second".to_string()).unwrap();
+
+    start_timer(&conn, first.id, at("2030-01-01 09:00:00")).unwrap();
+
+    assert!(start_timer(&conn, second.id, at("2030-01-01 09:05:00")).is_err());
+    assert!(conn.execute(
+      "INSERT INTO time_entries (task_id, started_at) VALUES (?, '2030-01-01 09:06:00')",
+      [&second.id],
+    ).is_err(), "The database should refuse a second running timer");
+  }
+
+  #[test]
+  fn test_stop_without_running_timer_fails() {
+    let conn = setup().unwrap();
+
+    assert!(matches!(stop_timer(&conn, at("2030-01-01 09:00:00")), Err(Error::QueryReturnedNoRows)));
+  }
+
+  #[test]
+  fn test_start_timer_on_missing_or_deleted_task_fails() {
+    let conn = setup().unwrap();
+    let task = insert_task(&conn, "Deleted".to_string()).unwrap();
+    delete_task(&conn, task.id).unwrap();
+
+    assert!(start_timer(&conn, task.id, at("2030-01-01 09:00:00")).is_err());
+    assert!(start_timer(&conn, 999, at("2030-01-01 09:00:00")).is_err());
+  }
+
+  #[test]
+  fn test_running_timer_counts_until_now() {
+    let conn = setup().unwrap();
+    let task = insert_task(&conn, "Running".to_string()).unwrap();
+
+    start_timer(&conn, task.id, at("2030-01-01 09:00:00")).unwrap();
+
+    assert_eq!(task_duration(&conn, task.id, at("2030-01-01 09:15:00")).unwrap(), Duration::minutes(15));
+  }
+
+  #[test]
+  fn test_report_splits_days_and_respects_since() {
+    let conn = setup().unwrap();
+    let first = insert_task(&conn, "First".to_string()).unwrap();
+    let second = insert_task(&conn, "Second".to_string()).unwrap();
+
+    start_timer(&conn, first.id, at("2030-01-01 08:00:00")).unwrap();
+    stop_timer(&conn, at("2030-01-01 09:00:00")).unwrap();
+    start_timer(&conn, first.id, at("2030-01-02 23:00:00")).unwrap();
+    stop_timer(&conn, at("2030-01-03 01:00:00")).unwrap();
+    start_timer(&conn, second.id, at("2030-01-03 10:00:00")).unwrap();
+    stop_timer(&conn, at("2030-01-03 10:30:00")).unwrap();
+
+    let report = time_report(&conn, at("2030-01-02 00:00:00"), at("2030-01-04 00:00:00")).unwrap();
+
+    assert_eq!(report.per_task, vec![
+      (first.id, "First".to_string(), Duration::hours(2)),
+      (second.id, "Second".to_string(), Duration::minutes(30)),
+    ]);
+    assert_eq!(report.per_day, vec![
+      (NaiveDate::from_ymd_opt(2030, 1, 2).unwrap(), Duration::hours(1)),
+      (NaiveDate::from_ymd_opt(2030, 1, 3).unwrap(), Duration::minutes(90)),
+    ]);
+  }
+
+  #[test]
+  fn test_format_duration() {
+    assert_eq!(format_duration(Duration::seconds(3725)), "01:02:05");
+    assert_eq!(format_duration(Duration::hours(30)), "30:00:00");
+  }
+}
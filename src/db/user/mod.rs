@@ -0,0 +1,184 @@
+use std::fmt;
+
+use rand::Rng;
+use rusqlite::{Connection, Error, OptionalExtension, Result};
+use sha2::{Digest, Sha256};
+
+use super::with_transaction;
+
+pub struct User {
+  pub id: u32,
+  pub name: String,
+  pub created_at: String,
+}
+
+impl fmt::Display for User {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "id = {}, name = {}, created_at = {}",
+           self.id, self.name, self.created_at)
+  }
+}
+
+pub fn build_users_db_table (conn: &Connection) -> Result<()> {
+  conn.execute(
+    "CREATE TABLE IF NOT EXISTS users (
+      id INTEGER PRIMARY KEY AUTOINCREMENT,
+      created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+      name TEXT NOT NULL UNIQUE,
+      token_hash TEXT NULL
+    )",
+    [],
+  )?;
+
+  Ok(())
+}
+
+/// Tokens are only ever stored as their SHA-256 digest.
+pub fn hash_token(token: &str) -> String {
+  format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn generate_token() -> String {
+  let bytes: [u8; 32] = rand::thread_rng().gen();
+  bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Create a user and return it together with its first API token.
+pub fn insert_user(conn: &Connection, name: String) -> Result<(User, String)> {
+  if name.is_empty() {
+    return Err(Error::InvalidParameterName("User name cannot be empty".to_string()));
+  }
+  let token = generate_token();
+  let sql = "INSERT INTO users (name, token_hash) VALUES (?, ?) RETURNING id, name, created_at";
+  let mut statement = conn.prepare(sql)?;
+
+  let user = statement.query_row(
+    [&name, &hash_token(&token)],
+    |row| {
+      Ok(User {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        created_at: row.get(2)?,
+      })
+    },
+  )?;
+
+  Ok((user, token))
+}
+
+/// Replace the API token of a user, invalidating the previous one.
+pub fn issue_token(conn: &Connection, name: &str) -> Result<String> {
+  let token = generate_token();
+  let updated = conn.execute(
+    "UPDATE users SET token_hash = ? WHERE name = ?",
+    [&hash_token(&token), name],
+  )?;
+  if updated == 0 {
+    return Err(Error::QueryReturnedNoRows);
+  }
+
+  Ok(token)
+}
+
+/// Remove a user. Its tasks lose their owner and are only reachable locally.
+pub fn delete_user(conn: &Connection, name: &str) -> Result<User> {
+  // The tasks must not point at a user that is gone
+  with_transaction(conn, |conn| {
+    let user = conn.query_row(
+      "SELECT id, name, created_at FROM users WHERE name = ?",
+      [name],
+      |row| {
+        Ok(User {
+          id: row.get(0)?,
+          name: row.get(1)?,
+          created_at: row.get(2)?,
+        })
+      },
+    )?;
+
+    conn.execute("UPDATE tasks SET owner_id = NULL WHERE owner_id = ?", [&user.id])?;
+    conn.execute("DELETE FROM users WHERE id = ?", [&user.id])?;
+
+    Ok(user)
+  })
+}
+
+/// Find the id of the user owning `token`, if any.
+pub fn find_user_id_by_token(conn: &Connection, token: &str) -> Result<Option<u32>> {
+  conn.query_row(
+    "SELECT id FROM users WHERE token_hash = ?",
+    [&hash_token(token)],
+    |row| row.get(0),
+  ).optional()
+}
+
+/// Name of the user `id`.
+pub fn find_user_name(conn: &Connection, id: u32) -> Result<String> {
+  conn.query_row("SELECT name FROM users WHERE id = ?", [&id], |row| row.get(0))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use super::super::connect_db;
+
+  fn setup() -> Result<Connection>{
+    // Set up test environment
+    connect_db(Some(true), None)
+  }
+
+  #[test]
+  fn test_insert_user_returns_working_token() {
+    let conn = setup().unwrap();
+
+    let (user, token) = insert_user(&conn, "alice".to_string()).unwrap();
+
+    assert_eq!(find_user_id_by_token(&conn, &token).unwrap(), Some(user.id));
+    assert_eq!(find_user_id_by_token(&conn, "wrong token").unwrap(), None);
+  }
+
+  #[test]
+  fn test_token_is_hashed_at_rest() {
+    let conn = setup().unwrap();
+
+    let (user, token) = insert_user(&conn, "alice".to_string()).unwrap();
+
+    let stored: String = conn
+      .query_row("SELECT token_hash FROM users WHERE id = ?", [&user.id], |row| row.get(0))
+      .unwrap();
+    assert_ne!(stored, token);
+    assert_eq!(stored, hash_token(&token));
+  }
+
+  #[test]
+  fn test_insert_user_rejects_empty_and_duplicate_names() {
+    let conn = setup().unwrap();
+
+    assert!(insert_user(&conn, "".to_string()).is_err());
+    insert_user(&conn, "alice".to_string()).unwrap();
+    assert!(insert_user(&conn, "alice".to_string()).is_err());
+  }
+
+  #[test]
+  fn test_issue_token_invalidates_previous_token() {
+    let conn = setup().unwrap();
+    let (user, old_token) = insert_user(&conn, "alice".to_string()).unwrap();
+
+    let new_token = issue_token(&conn, "alice").unwrap();
+
+    assert_eq!(find_user_id_by_token(&conn, &old_token).unwrap(), None);
+    assert_eq!(find_user_id_by_token(&conn, &new_token).unwrap(), Some(user.id));
+    assert!(issue_token(&conn, "nobody").is_err());
+  }
+
+  #[test]
+  fn test_delete_user_revokes_token() {
+    let conn = setup().unwrap();
+    let (_, token) = insert_user(&conn, "alice".to_string()).unwrap();
+
+    delete_user(&conn, "alice").unwrap();
+
+    assert_eq!(find_user_id_by_token(&conn, &token).unwrap(), None);
+    assert!(delete_user(&conn, "alice").is_err());
+  }
+}
@@ -0,0 +1,386 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Output};
+
+use rusqlite::Connection;
+
+use crate::db::build_db;
+use crate::db::sync::{insert_row, merge_rows, read_rows, sync_with_peer, Conflict, SyncChange, SyncRow};
+
+/// Environment variable naming the git working directory tasks are mirrored
+/// to after every command.
+pub const GIT_DIR_VAR: &str = "TODO_GIT_DIR";
+
+/// File of the working directory holding one task per line.
+pub const TASKS_FILE: &str = "tasks.txt";
+
+const REMOTE: &str = "origin";
+
+const HEADER: &str = "# uuid\tis_done\tcreated_at\tcompleted_at\tcompleted_by\tremind_at\treminded_at\tdeleted_at\t\
+updated_at\tname_updated_at\tstatus_updated_at\tremind_updated_at\tdeleted_updated_at\tname";
+
+#[derive(Debug, Default)]
+pub struct GitSyncReport {
+  /// Changes pulled from the remote into the database.
+  pub into_local: Vec<SyncChange>,
+  /// Fields changed on both sides of a conflicting merge, decided by their clocks.
+  pub conflicts: Vec<Conflict>,
+  pub pushed: bool,
+}
+
+fn escape(value: &str) -> String {
+  value.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n")
+}
+
+fn unescape(value: &str) -> String {
+  let mut unescaped = String::with_capacity(value.len());
+  let mut chars = value.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      unescaped.push(c);
+      continue;
+    }
+    match chars.next() {
+      Some('t') => unescaped.push('\t'),
+      Some('n') => unescaped.push('\n'),
+      Some(other) => unescaped.push(other),
+      None => unescaped.push('\\'),
+    }
+  }
+  unescaped
+}
+
+fn optional(value: &Option<String>) -> String {
+  value.as_deref().map(escape).unwrap_or_default()
+}
+
+/// One tab separated line per task, the UUID first so lines sort and merge
+/// by task. Empty fields stand for missing values.
+pub fn format_line(row: &SyncRow) -> String {
+  [
+    escape(&row.uuid),
+    (row.is_done as u8).to_string(),
+    escape(&row.created_at),
+    optional(&row.completed_at),
+    optional(&row.completed_by),
+    optional(&row.remind_at),
+    optional(&row.reminded_at),
+    optional(&row.deleted_at),
+    escape(&row.updated_at),
+    escape(&row.name_updated_at),
+    escape(&row.status_updated_at),
+    escape(&row.remind_updated_at),
+    escape(&row.deleted_updated_at),
+    escape(&row.name),
+  ].join("\t")
+}
+
+pub fn parse_line(line: &str) -> io::Result<SyncRow> {
+  let fields: Vec<String> = line.split('\t').map(unescape).collect();
+  let [uuid, is_done, created_at, completed_at, completed_by, remind_at, reminded_at, deleted_at, updated_at,
+       name_updated_at, status_updated_at, remind_updated_at, deleted_updated_at, name] = <[String; 14]>::try_from(fields)
+    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid task line '{line}'")))?;
+  let optional = |value: String| (!value.is_empty()).then_some(value);
+
+  Ok(SyncRow {
+    uuid,
+    name,
+    created_at,
+    is_done: is_done == "1",
+    completed_at: optional(completed_at),
+    completed_by: optional(completed_by),
+    remind_at: optional(remind_at),
+    reminded_at: optional(reminded_at),
+    deleted_at: optional(deleted_at),
+    updated_at,
+    name_updated_at,
+    status_updated_at,
+    remind_updated_at,
+    deleted_updated_at,
+  })
+}
+
+/// Parse a tasks file, merging lines that share a UUID.
+pub fn parse_tasks(text: &str, conflicts: &mut Vec<Conflict>) -> io::Result<BTreeMap<String, SyncRow>> {
+  let mut rows: BTreeMap<String, SyncRow> = BTreeMap::new();
+
+  for line in text.lines().filter(|line| !line.is_empty() && !line.starts_with('#')) {
+    let row = parse_line(line)?;
+    let row = match rows.get(&row.uuid) {
+      Some(existing) => merge_rows(existing, &row, None, conflicts),
+      None => row,
+    };
+    rows.insert(row.uuid.clone(), row);
+  }
+
+  Ok(rows)
+}
+
+pub fn format_tasks(rows: &BTreeMap<String, SyncRow>) -> String {
+  let mut text = format!("{HEADER}\n");
+  for row in rows.values() {
+    text.push_str(&format_line(row));
+    text.push('\n');
+  }
+  text
+}
+
+/// Write every task, deleted ones included, to the tasks file of `dir`.
+/// Returns whether the file changed.
+pub fn mirror_tasks(conn: &Connection, dir: &Path) -> io::Result<bool> {
+  let text = format_tasks(&read_rows(conn).map_err(io::Error::other)?);
+  let path = dir.join(TASKS_FILE);
+
+  if fs::read_to_string(&path).ok().as_deref() == Some(text.as_str()) {
+    return Ok(false);
+  }
+  fs::write(path, text)?;
+
+  Ok(true)
+}
+
+fn git_output(dir: &Path, args: &[&str]) -> io::Result<Output> {
+  Command::new("git").arg("-C").arg(dir).args(args).output()
+}
+
+/// Run git in `dir`, failing with its error output when it does not succeed.
+fn git(dir: &Path, args: &[&str]) -> io::Result<String> {
+  let output = git_output(dir, args)?;
+  if !output.status.success() {
+    return Err(io::Error::other(format!(
+      "git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim(),
+    )));
+  }
+
+  Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Stage the tasks file and commit it if it changed.
+fn commit_tasks(dir: &Path, message: &str) -> io::Result<bool> {
+  git(dir, &["add", TASKS_FILE])?;
+  if git_output(dir, &["diff", "--cached", "--quiet"])?.status.success() {
+    return Ok(false);
+  }
+  git(dir, &["commit", "--quiet", "-m", message])?;
+
+  Ok(true)
+}
+
+/// Content of the tasks file at a merge stage, empty when that side has none.
+fn staged_tasks(dir: &Path, stage: u8) -> io::Result<String> {
+  let output = git_output(dir, &["show", &format!(":{stage}:{TASKS_FILE}")])?;
+  Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Settle a conflicting merge of the tasks file by merging both versions
+/// task by task, the newest change of each field winning.
+fn resolve_conflict(dir: &Path, conflicts: &mut Vec<Conflict>) -> io::Result<()> {
+  let unmerged = git(dir, &["diff", "--name-only", "--diff-filter=U"])?;
+  if unmerged.lines().any(|path| path != TASKS_FILE) {
+    git(dir, &["merge", "--abort"])?;
+    return Err(io::Error::other(format!("Merge conflicts outside {TASKS_FILE}, resolve them by hand")));
+  }
+
+  let ours = parse_tasks(&staged_tasks(dir, 2)?, conflicts)?;
+  let mut merged = parse_tasks(&staged_tasks(dir, 3)?, conflicts)?;
+  for (uuid, row) in ours {
+    let row = match merged.get(&uuid) {
+      Some(theirs) => merge_rows(&row, theirs, None, conflicts),
+      None => row,
+    };
+    merged.insert(uuid, row);
+  }
+
+  fs::write(dir.join(TASKS_FILE), format_tasks(&merged))?;
+  git(dir, &["add", TASKS_FILE])?;
+  git(dir, &["commit", "--quiet", "--no-edit"])?;
+
+  Ok(())
+}
+
+/// Name the last sync with the git working directory `dir` is recorded
+/// under, distinct from database files and from other directories.
+fn git_peer_name(dir: &Path) -> String {
+  let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+  format!("git:{}", dir.display())
+}
+
+/// Merge the tasks file into `conn` through a scratch database, so the
+/// same rules as `sync` apply.
+fn import_tasks(conn: &Connection, dir: &Path, report: &mut GitSyncReport) -> io::Result<()> {
+  let text = fs::read_to_string(dir.join(TASKS_FILE)).unwrap_or_default();
+  let rows = parse_tasks(&text, &mut report.conflicts)?;
+
+  let scratch = Connection::open_in_memory().map_err(io::Error::other)?;
+  build_db(&scratch).map_err(io::Error::other)?;
+  for row in rows.values() {
+    insert_row(&scratch, row).map_err(io::Error::other)?;
+  }
+  let sync = sync_with_peer(conn, &scratch, &git_peer_name(dir), false).map_err(io::Error::other)?;
+  report.into_local = sync.into_local;
+
+  Ok(())
+}
+
+/// Commit the tasks to the git working directory `dir`, pull and merge the
+/// remote branch, resolving conflicts on the tasks file by task UUID, load
+/// the result into the database and push it back. Without an `origin`
+/// remote the tasks are only committed.
+pub fn git_sync(conn: &Connection, dir: &Path) -> io::Result<GitSyncReport> {
+  if git(dir, &["rev-parse", "--is-inside-work-tree"])?.trim() != "true" {
+    return Err(io::Error::other(format!("{} is not a git working directory", dir.display())));
+  }
+  let mut report = GitSyncReport::default();
+
+  mirror_tasks(conn, dir)?;
+  commit_tasks(dir, "Update tasks")?;
+
+  let has_remote = git(dir, &["remote"])?.lines().any(|remote| remote == REMOTE);
+  let branch = git(dir, &["symbolic-ref", "--short", "HEAD"])?.trim().to_string();
+  if has_remote && !git(dir, &["ls-remote", "--heads", REMOTE, &branch])?.trim().is_empty() {
+    let pull = git_output(dir, &["pull", "--quiet", "--no-rebase", "--no-edit", "--allow-unrelated-histories",
+                                 REMOTE, &branch])?;
+    if !pull.status.success() {
+      if git(dir, &["diff", "--name-only", "--diff-filter=U"])?.trim().is_empty() {
+        return Err(io::Error::other(format!("git pull failed: {}", String::from_utf8_lossy(&pull.stderr).trim())));
+      }
+      resolve_conflict(dir, &mut report.conflicts)?;
+    }
+  }
+
+  import_tasks(conn, dir, &mut report)?;
+  mirror_tasks(conn, dir)?;
+  commit_tasks(dir, "Merge tasks")?;
+
+  if has_remote {
+    git(dir, &["push", "--quiet", "-u", REMOTE, &branch])?;
+    report.pushed = true;
+  }
+
+  Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::connect_db;
+  use crate::db::task::{delete_task, insert_task, read_tasks, update_task_status};
+  use std::path::PathBuf;
+
+  /// A bare repository standing for the remote and clones of it, removed
+  /// when the test ends.
+  struct TempRepos {
+    root: PathBuf,
+  }
+
+  impl Drop for TempRepos {
+    fn drop(&mut self) {
+      let _ = fs::remove_dir_all(&self.root);
+    }
+  }
+
+  impl TempRepos {
+    fn clone(&self, name: &str) -> PathBuf {
+      let dir = self.root.join(name);
+      git(&self.root, &["clone", "--quiet", "remote.git", name]).unwrap();
+      git(&dir, &["config", "user.name", "Test"]).unwrap();
+      git(&dir, &["config", "user.email", "test@example.com"]).unwrap();
+      git(&dir, &["config", "commit.gpgsign", "false"]).unwrap();
+      dir
+    }
+  }
+
+  fn setup(name: &str) -> TempRepos {
+    // Set up a bare remote of its own for each test
+    let root = std::env::temp_dir().join(format!("rust-to-do-list-{}-git-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    git(&root, &["init", "--quiet", "--bare", "remote.git"]).unwrap();
+    TempRepos { root }
+  }
+
+  fn names(conn: &Connection) -> Vec<String> {
+    let mut names: Vec<String> = read_tasks(conn).unwrap().into_iter().map(|task| task.name).collect();
+    names.sort();
+    names
+  }
+
+  #[test]
+  fn test_line_round_trip_escapes_separators() {
+    let conn = connect_db(Some(true), None).unwrap();
+    let task = insert_task(&conn, "Tab\there, line\nbreak and \\".to_string()).unwrap();
+    update_task_status(&conn, task.id, true).unwrap();
+
+    let row = read_rows(&conn).unwrap().remove(&task.uuid).unwrap();
+    let line = format_line(&row);
+
+    assert_eq!(line.lines().count(), 1);
+    assert_eq!(parse_line(&line).unwrap(), row);
+    assert!(parse_line("not a task").is_err());
+  }
+
+  #[test]
+  fn test_git_sync_exchanges_tasks_through_remote() {
+    let repos = setup("exchange");
+    let (first_dir, second_dir) = (repos.clone("first"), repos.clone("second"));
+    let (first, second) = (connect_db(Some(true), None).unwrap(), connect_db(Some(true), None).unwrap());
+    insert_task(&first, "First task".to_string()).unwrap();
+    let shared = insert_task(&second, "Second task".to_string()).unwrap();
+
+    assert!(git_sync(&first, &first_dir).unwrap().pushed);
+    let report = git_sync(&second, &second_dir).unwrap();
+    assert_eq!(report.into_local.len(), 1);
+    git_sync(&first, &first_dir).unwrap();
+
+    assert_eq!(names(&first), vec!["First task", "Second task"]);
+    assert_eq!(names(&second), vec!["First task", "Second task"]);
+
+    // A deletion travels as a tombstone line
+    delete_task(&second, shared.id).unwrap();
+    git_sync(&second, &second_dir).unwrap();
+    git_sync(&first, &first_dir).unwrap();
+    assert_eq!(names(&first), vec!["First task"]);
+
+    // The last sync is remembered per working directory
+    let peers: Vec<String> = first.prepare("SELECT peer FROM sync_peers").unwrap()
+      .query_map([], |row| row.get(0)).unwrap().collect::<rusqlite::Result<_>>().unwrap();
+    assert_eq!(peers, vec![git_peer_name(&first_dir)]);
+  }
+
+  #[test]
+  fn test_git_sync_resolves_conflicting_lines_by_uuid() {
+    let repos = setup("conflict");
+    let (first_dir, second_dir) = (repos.clone("first"), repos.clone("second"));
+    let (first, second) = (connect_db(Some(true), None).unwrap(), connect_db(Some(true), None).unwrap());
+    insert_task(&first, "Shared".to_string()).unwrap();
+    git_sync(&first, &first_dir).unwrap();
+    git_sync(&second, &second_dir).unwrap();
+
+    // Both sides edit the same line, the second one last
+    let id = read_tasks(&second).unwrap()[0].id;
+    update_task_status(&first, id, true).unwrap();
+    update_task_status(&second, id, true).unwrap();
+    update_task_status(&second, id, false).unwrap();
+    second.execute("UPDATE tasks SET status_updated_at = '2999-01-01 00:00:00.000'", []).unwrap();
+    git_sync(&second, &second_dir).unwrap();
+
+    let report = git_sync(&first, &first_dir).unwrap();
+
+    assert_eq!(report.conflicts.len(), 1);
+    assert_eq!(report.conflicts[0].field, "is_done");
+    assert!(!read_tasks(&first).unwrap()[0].is_done);
+    let text = fs::read_to_string(first_dir.join(TASKS_FILE)).unwrap();
+    assert!(!text.contains("<<<<<<<"), "Conflict markers should be resolved");
+    assert_eq!(text.lines().filter(|line| !line.starts_with('#')).count(), 1);
+  }
+
+  #[test]
+  fn test_git_sync_outside_a_work_tree_fails() {
+    let repos = setup("outside");
+    let conn = connect_db(Some(true), None).unwrap();
+
+    assert!(git_sync(&conn, &repos.root.join("remote.git")).is_err());
+  }
+}
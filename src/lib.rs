@@ -1,29 +1,139 @@
 mod commands;
+mod completions;
 mod db;
+mod gitsync;
+mod logging;
+mod profile;
+mod remind;
+mod render;
+mod server;
+mod shell;
+mod todo_list;
+mod tui;
+
+use std::process;
 
 use clap::Parser;
-pub use crate::db::connect_db;
-pub use crate::commands::{Commands, command_switch};
+use crate::db::connect_db_file;
+use crate::logging::{data_dir, level_filter, Logger, LOG_FILE};
+use crate::profile::{profile_database, DEFAULT_PROFILE, TEST_PROFILE};
+use crate::render::terminal_renderer;
+pub use crate::db::{connect_db, with_transaction};
+#[cfg(feature = "json-store")]
+pub use crate::db::json_store::{json_path, JsonStore, STORAGE_VAR};
+pub use crate::db::selection::TaskFilter;
+pub use crate::db::store::{migrate_tasks, MemoryStore, StoreError, StoreResult, TaskStore};
+pub use crate::db::task::Task;
+pub use crate::todo_list::{StorageError, TodoError, TodoList, TodoListBuilder};
+pub use crate::commands::{CommandError, CommandOutcome, Commands, UserCommands, command_switch};
+pub use crate::render::{JsonRenderer, Renderer, TextRenderer};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
     #[command(subcommand)]
     cmd: Commands,
+
+    #[command(flatten)]
+    options: GlobalOptions,
 }
 
-pub fn run(){
-  let args = Args::parse();
+/// Flags accepted before or after any command.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct GlobalOptions {
+    /// Print the outcome as a JSON document instead of text
+    #[arg(long = "json", global = true, default_value_t = false)]
+    json: bool,
+
+    /// Only print results, warnings and errors, no progress messages
+    #[arg(short = 'q', long = "quiet", global = true, default_value_t = false, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Print more details; twice also prints every SQL statement with its duration
+    #[arg(short = 'v', long = "verbose", global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Also append the log to rust-to-do-list.log in the data directory
+    #[arg(long = "log-file", global = true, default_value_t = false)]
+    log_file: bool,
 
-  let is_dry_test = match args.cmd {
-    Commands::Add { name: _, dry_test } => dry_test,
-    Commands::Get { dry_test } => dry_test,
-    Commands::Update { id: _, done: _, dry_test } => dry_test,
-    Commands::Delete { id: _, dry_test } => dry_test,
+    /// Show what the command would change, then roll it back
+    #[arg(long = "dry-run", global = true, default_value_t = false)]
+    dry_run: bool,
+
+    /// Profile whose database to use: default, test or one of the profiles file
+    #[arg(short = 'p', long = "profile", global = true, conflicts_with = "dry_test")]
+    profile: Option<String>,
+
+    /// Use the test database, same as --profile test
+    #[arg(short = 't', long = "dry-test", global = true, default_value_t = false)]
+    dry_test: bool,
+}
+
+impl GlobalOptions {
+    /// Name of the profile selected by `--profile` or `-t`.
+    fn profile_name(&self) -> &str {
+        match (&self.profile, self.dry_test) {
+            (Some(profile), _) => profile,
+            (None, true) => TEST_PROFILE,
+            (None, false) => DEFAULT_PROFILE,
+        }
+    }
+}
+
+pub fn run(){
+  let args = match Args::try_parse() {
+    Ok(args) => args,
+    // Help and version are printed on stdout and succeed
+    Err(err) if !err.use_stderr() => err.exit(),
+    Err(err) => fail(CommandError::Usage(err.to_string().trim_end().to_string())),
   };
+  let json = args.options.json;
+  start_logging(&args.options);
+
+  let database = profile_database(args.options.profile_name()).unwrap_or_else(|err| fail(err));
+
+  #[cfg(feature = "json-store")]
+  if std::env::var(STORAGE_VAR).as_deref() == Ok("json") {
+    let store = JsonStore::open_exclusive(json_path(&database)).unwrap_or_else(|err| {
+      fail(CommandError::Storage(format!("Error opening the JSON storage: {err}")))
+    });
+    return finish(command_switch(args, &store), json);
+  }
+
+  let conn = connect_db_file(&database).unwrap_or_else(|err| {
+    fail(CommandError::Storage(format!("Error opening the database: {err}")))
+  });
 
-  let conn = connect_db(Some(false), 
-    Some(is_dry_test)).unwrap();
+  finish(command_switch(args, &conn), json);
+}
+
+/// Render the outcome of the command, or report its error and exit with the
+/// code of `CommandError`.
+fn finish(result: Result<CommandOutcome, CommandError>, json: bool) {
+  let rendered = result.and_then(|outcome| {
+    terminal_renderer(json).render(&outcome)
+      .map_err(|err| CommandError::Storage(format!("Error printing the outcome: {err}")))
+  });
+  if let Err(err) = rendered {
+    fail(err);
+  }
+}
+
+/// Install the logger for the `-q`, `-v` and `--log-file` flags. A log file
+/// that cannot be opened is reported and the command runs without it.
+fn start_logging(options: &GlobalOptions) {
+  let mut logger = Logger::new(level_filter(options.quiet, options.verbose));
+  if options.log_file {
+    let path = data_dir().join(LOG_FILE);
+    if let Err(err) = logger.log_to_file(&path) {
+      eprintln!("Error opening the log file {}: {err}", path.display());
+    }
+  }
+  let _ = logger.install();
+}
 
-  command_switch(args, &conn);
+fn fail(err: CommandError) -> ! {
+  eprintln!("{err}");
+  process::exit(err.exit_code());
 }
\ No newline at end of file
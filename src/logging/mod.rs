@@ -0,0 +1,139 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use chrono::Local;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+/// Name of the log file written with `--log-file`, in `data_dir()`.
+pub const LOG_FILE: &str = "rust-to-do-list.log";
+
+/// Target of the SQL statements logged at trace level.
+pub const SQL_TARGET: &str = "sql";
+
+/// How much to log for the `-q` and `-v` flags: warnings and errors only
+/// when quiet, progress messages by default, details with `-v` and every
+/// SQL statement with `-vv`.
+pub fn level_filter(quiet: bool, verbose: u8) -> LevelFilter {
+  match (quiet, verbose) {
+    (true, _) => LevelFilter::Warn,
+    (false, 0) => LevelFilter::Info,
+    (false, 1) => LevelFilter::Debug,
+    (false, _) => LevelFilter::Trace,
+  }
+}
+
+/// Directory for the files of the application that are not tasks:
+/// `$XDG_DATA_HOME/rust-to-do-list`, `~/.local/share/rust-to-do-list` or,
+/// without a home, the current directory.
+pub fn data_dir() -> PathBuf {
+  match (env::var_os("XDG_DATA_HOME"), env::var_os("HOME")) {
+    (Some(data), _) if !data.is_empty() => PathBuf::from(data).join("rust-to-do-list"),
+    (_, Some(home)) => PathBuf::from(home).join(".local").join("share").join("rust-to-do-list"),
+    _ => PathBuf::from("."),
+  }
+}
+
+/// Logger of the command line: progress messages on the standard error
+/// and, optionally, every record with its time appended to a file.
+pub struct Logger {
+  level: LevelFilter,
+  file: Option<Mutex<File>>,
+}
+
+impl Logger {
+  pub fn new(level: LevelFilter) -> Logger {
+    Logger { level, file: None }
+  }
+
+  /// Also append the records to `path`, creating its directory if needed.
+  pub fn log_to_file(&mut self, path: &Path) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir)?;
+    }
+    self.file = Some(Mutex::new(OpenOptions::new().create(true).append(true).open(path)?));
+    Ok(())
+  }
+
+  /// Install the logger for the whole process.
+  pub fn install(self) -> Result<(), log::SetLoggerError> {
+    let level = self.level;
+    log::set_boxed_logger(Box::new(self))?;
+    log::set_max_level(level);
+    Ok(())
+  }
+}
+
+impl Log for Logger {
+  fn enabled(&self, metadata: &Metadata) -> bool {
+    metadata.level() <= self.level
+  }
+
+  fn log(&self, record: &Record) {
+    if !self.enabled(record.metadata()) {
+      return;
+    }
+
+    // Progress messages read as plain lines, the rest says what it is
+    let _ = match record.level() {
+      Level::Info => writeln!(io::stderr(), "{}", record.args()),
+      level => writeln!(io::stderr(), "{}: {}", level.as_str().to_lowercase(), record.args()),
+    };
+    if let Some(file) = &self.file {
+      if let Ok(mut file) = file.lock() {
+        let _ = writeln!(file, "{} {} {}: {}",
+                         Local::now().format("%Y-%m-%d %H:%M:%S%.3f"), record.level(), record.target(), record.args());
+      }
+    }
+  }
+
+  fn flush(&self) {
+    if let Some(file) = &self.file {
+      if let Ok(mut file) = file.lock() {
+        let _ = file.flush();
+      }
+    }
+  }
+}
+
+/// Profiler of SQLite connections, logging each statement and how long it
+/// took at trace level.
+pub fn log_sql(sql: &str, duration: Duration) {
+  let sql = sql.split_whitespace().collect::<Vec<_>>().join(" ");
+  log::trace!(target: SQL_TARGET, "{sql} ({:.3} ms)", duration.as_secs_f64() * 1000.0);
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_level_filter() {
+    assert_eq!(level_filter(true, 0), LevelFilter::Warn);
+    assert_eq!(level_filter(false, 0), LevelFilter::Info);
+    assert_eq!(level_filter(false, 1), LevelFilter::Debug);
+    assert_eq!(level_filter(false, 2), LevelFilter::Trace);
+    assert_eq!(level_filter(false, 5), LevelFilter::Trace);
+  }
+
+  #[test]
+  fn test_logger_appends_enabled_records_to_file() {
+    let path = std::env::temp_dir().join(format!("rust-to-do-list-{}-log", std::process::id())).join(LOG_FILE);
+    let _ = fs::remove_file(&path);
+    let mut logger = Logger::new(LevelFilter::Debug);
+    logger.log_to_file(&path).unwrap();
+
+    for (level, message) in [(Level::Debug, "Opening tasks.db"), (Level::Trace, "SELECT 1")] {
+      logger.log(&Record::builder().level(level).target("rust_to_do_list").args(format_args!("{message}")).build());
+    }
+    logger.flush();
+
+    let text = fs::read_to_string(&path).unwrap();
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    assert!(text.trim_end().ends_with("DEBUG rust_to_do_list: Opening tasks.db"));
+    assert!(!text.contains("SELECT 1"), "Records above the level are dropped");
+  }
+}
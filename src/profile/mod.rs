@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::commands::CommandError;
+
+/// Environment variable pointing at the profiles file, instead of the one
+/// in the configuration directory.
+pub const CONFIG_VAR: &str = "TODO_CONFIG";
+
+/// Profile used without `--profile`, on `tasks.db`.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Profile selected by `-t`, on `test_tasks.db`.
+pub const TEST_PROFILE: &str = "test";
+
+/// The profiles file: `$TODO_CONFIG`, or `profiles` in
+/// `$XDG_CONFIG_HOME/rust-to-do-list` or `~/.config/rust-to-do-list`.
+pub fn config_path() -> PathBuf {
+  if let Some(path) = env::var_os(CONFIG_VAR).filter(|path| !path.is_empty()) {
+    return PathBuf::from(path);
+  }
+  let dir = match (env::var_os("XDG_CONFIG_HOME"), env::var_os("HOME")) {
+    (Some(config), _) if !config.is_empty() => PathBuf::from(config),
+    (_, Some(home)) => PathBuf::from(home).join(".config"),
+    _ => PathBuf::from("."),
+  };
+  dir.join("rust-to-do-list").join("profiles")
+}
+
+/// Expand a leading `~/` to the home directory.
+fn expand_home(path: &str) -> PathBuf {
+  match (path.strip_prefix("~/"), env::var_os("HOME")) {
+    (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+    _ => PathBuf::from(path),
+  }
+}
+
+/// Parse a profiles file: one `name = database file` per line, blank lines
+/// and `#` comments ignored. Relative files are taken from the current
+/// directory, like `tasks.db`.
+pub fn parse_profiles(text: &str) -> Result<BTreeMap<String, PathBuf>, String> {
+  let mut profiles = BTreeMap::new();
+
+  for (number, line) in text.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+      continue;
+    }
+    match line.split_once('=') {
+      Some((name, path)) if !name.trim().is_empty() && !path.trim().is_empty() => {
+        profiles.insert(name.trim().to_string(), expand_home(path.trim()));
+      }
+      _ => return Err(format!("line {}: expected 'name = database file', found '{line}'", number + 1)),
+    }
+  }
+
+  Ok(profiles)
+}
+
+/// Database file of the profile `name`. `default` and `test` work without a
+/// profiles file; the file can override them and add others.
+pub fn profile_database(name: &str) -> Result<PathBuf, CommandError> {
+  let path = config_path();
+  let profiles = match fs::read_to_string(&path) {
+    Ok(text) => parse_profiles(&text)
+      .map_err(|err| CommandError::Invalid(format!("Invalid profiles file {}: {err}", path.display())))?,
+    Err(err) if err.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+    Err(err) => return Err(CommandError::Storage(format!("Error reading {}: {err}", path.display()))),
+  };
+
+  match (profiles.get(name), name) {
+    (Some(database), _) => Ok(database.clone()),
+    (None, DEFAULT_PROFILE) => Ok(PathBuf::from("tasks.db")),
+    (None, TEST_PROFILE) => Ok(PathBuf::from("test_tasks.db")),
+    (None, _) => Err(CommandError::Usage(format!("Unknown profile {name}, add it to {}", path.display()))),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_profiles() {
+    let profiles = parse_profiles("# Work and play\nwork = /data/work.db\n\n  sandbox=sandbox.db  \n").unwrap();
+
+    assert_eq!(profiles.len(), 2);
+    assert_eq!(profiles["work"], PathBuf::from("/data/work.db"));
+    assert_eq!(profiles["sandbox"], PathBuf::from("sandbox.db"));
+  }
+
+  #[test]
+  fn test_parse_profiles_reports_the_bad_line() {
+    let err = parse_profiles("work = work.db\nsandbox\n").unwrap_err();
+    assert!(err.starts_with("line 2:"), "{err}");
+    assert!(parse_profiles(" = work.db").is_err());
+  }
+}
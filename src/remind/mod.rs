@@ -0,0 +1,221 @@
+use std::io::{self, Write};
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use chrono::Utc;
+use rusqlite::{Connection, Result};
+use serde_json::json;
+
+use crate::db::reminder::{claim_reminder, local_remind_at, read_due_reminders, release_reminder, Reminder, REMIND_AT_FORMAT};
+
+/// Somewhere a due reminder can be delivered to.
+pub trait ReminderSink {
+  fn notify(&mut self, reminder: &Reminder) -> io::Result<()>;
+}
+
+/// Print reminders on the standard output.
+pub struct StdoutSink;
+
+impl ReminderSink for StdoutSink {
+  fn notify(&mut self, reminder: &Reminder) -> io::Result<()> {
+    let mut out = io::stdout();
+    writeln!(out, "Reminder: task {} \"{}\" was due at {}",
+             reminder.task.id, reminder.task.name, local_remind_at(&reminder.remind_at))?;
+    out.flush()
+  }
+}
+
+/// Run a shell command for each reminder, with the task passed through the
+/// `TODO_TASK_ID`, `TODO_TASK_NAME` and `TODO_REMIND_AT` variables, the
+/// last one in UTC.
+pub struct CommandSink {
+  pub command: String,
+}
+
+impl ReminderSink for CommandSink {
+  fn notify(&mut self, reminder: &Reminder) -> io::Result<()> {
+    let status = Command::new("sh")
+      .arg("-c")
+      .arg(&self.command)
+      .env("TODO_TASK_ID", reminder.task.id.to_string())
+      .env("TODO_TASK_NAME", &reminder.task.name)
+      .env("TODO_REMIND_AT", &reminder.remind_at)
+      .status()?;
+
+    if status.success() {
+      Ok(())
+    } else {
+      Err(io::Error::other(format!("Reminder command exited with {status}")))
+    }
+  }
+}
+
+/// Write each reminder as one JSON line to a listening Unix socket, with
+/// `remind_at` in UTC.
+#[cfg(unix)]
+pub struct UnixSocketSink {
+  pub path: String,
+}
+
+#[cfg(unix)]
+impl ReminderSink for UnixSocketSink {
+  fn notify(&mut self, reminder: &Reminder) -> io::Result<()> {
+    let mut stream = std::os::unix::net::UnixStream::connect(&self.path)?;
+    let line = json!({
+      "id": reminder.task.id,
+      "name": reminder.task.name,
+      "remind_at": reminder.remind_at,
+    });
+    writeln!(stream, "{line}")
+  }
+}
+
+/// Deliver every reminder due at `now`, in UTC, to all sinks and return how
+/// many fired. Each one is claimed in the database first so it fires only
+/// once, even with several daemons polling the same file. A reminder that
+/// a sink failed to deliver is released, to be tried again on the next poll.
+pub fn poll_reminders(conn: &Connection, sinks: &mut [Box<dyn ReminderSink>], now: &str) -> Result<usize> {
+  let mut fired = 0;
+
+  for reminder in read_due_reminders(conn, now)? {
+    if !claim_reminder(conn, reminder.task.id)? {
+      continue;
+    }
+    let mut delivered = true;
+    for sink in sinks.iter_mut() {
+      if let Err(err) = sink.notify(&reminder) {
+        log::warn!("Error delivering reminder for task {}: {}", reminder.task.id, err);
+        delivered = false;
+      }
+    }
+    if delivered {
+      fired += 1;
+    } else {
+      release_reminder(conn, reminder.task.id)?;
+    }
+  }
+
+  Ok(fired)
+}
+
+/// Poll for due reminders every `interval` until the process is stopped,
+/// or a single time when `once` is set. A failed poll, such as one finding
+/// the database busy, is logged and retried on the next one.
+pub fn run_reminders(conn: &Connection, mut sinks: Vec<Box<dyn ReminderSink>>, interval: Duration, once: bool) -> Result<()> {
+  loop {
+    let now = Utc::now().format(REMIND_AT_FORMAT).to_string();
+    match poll_reminders(conn, &mut sinks, &now) {
+      Err(err) if !once => log::warn!("Error polling reminders: {err}"),
+      result => { result?; },
+    }
+    if once {
+      return Ok(());
+    }
+    thread::sleep(interval);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::connect_db;
+  use crate::db::reminder::{parse_remind_at, set_task_reminder};
+  use crate::db::task::insert_task;
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  struct CollectSink {
+    names: Rc<RefCell<Vec<String>>>,
+  }
+
+  impl ReminderSink for CollectSink {
+    fn notify(&mut self, reminder: &Reminder) -> io::Result<()> {
+      self.names.borrow_mut().push(reminder.task.name.clone());
+      Ok(())
+    }
+  }
+
+  struct FailingSink;
+
+  impl ReminderSink for FailingSink {
+    fn notify(&mut self, _reminder: &Reminder) -> io::Result<()> {
+      Err(io::Error::other("Sink is down"))
+    }
+  }
+
+  /// The stored form of the local time `value`.
+  fn utc(value: &str) -> String {
+    parse_remind_at(value).unwrap()
+  }
+
+  fn setup() -> Connection {
+    // Set up test environment with one due task
+    let conn = connect_db(Some(true), None).unwrap();
+    let task = insert_task(&conn, "Pay rent".to_string()).unwrap();
+    set_task_reminder(&conn, task.id, "2030-01-01 08:00").unwrap();
+    conn
+  }
+
+  #[test]
+  fn test_poll_fires_each_reminder_once() {
+    let conn = setup();
+    let names = Rc::new(RefCell::new(Vec::new()));
+    let mut sinks: Vec<Box<dyn ReminderSink>> = vec![Box::new(CollectSink { names: names.clone() })];
+
+    assert_eq!(poll_reminders(&conn, &mut sinks, &utc("2029-12-31 00:00")).unwrap(), 0);
+    assert_eq!(poll_reminders(&conn, &mut sinks, &utc("2030-01-01 09:00")).unwrap(), 1);
+    assert_eq!(poll_reminders(&conn, &mut sinks, &utc("2030-01-01 10:00")).unwrap(), 0);
+
+    assert_eq!(*names.borrow(), vec!["Pay rent".to_string()]);
+  }
+
+  #[test]
+  fn test_failed_delivery_is_retried() {
+    let conn = setup();
+    let mut failing: Vec<Box<dyn ReminderSink>> = vec![Box::new(FailingSink)];
+    assert_eq!(poll_reminders(&conn, &mut failing, &utc("2030-01-01 09:00")).unwrap(), 0);
+
+    let names = Rc::new(RefCell::new(Vec::new()));
+    let mut sinks: Vec<Box<dyn ReminderSink>> = vec![Box::new(CollectSink { names: names.clone() })];
+    assert_eq!(poll_reminders(&conn, &mut sinks, &utc("2030-01-01 10:00")).unwrap(), 1);
+    assert_eq!(*names.borrow(), vec!["Pay rent".to_string()]);
+  }
+
+  #[test]
+  fn test_command_sink_receives_task_environment() {
+    let conn = setup();
+    let out = std::env::temp_dir().join(format!("rust-to-do-list-{}-remind-command.txt", std::process::id()));
+    let _ = std::fs::remove_file(&out);
+    let command = format!("echo \"$TODO_TASK_ID $TODO_TASK_NAME\" > {}", out.display());
+    let mut sinks: Vec<Box<dyn ReminderSink>> = vec![Box::new(CommandSink { command })];
+
+    poll_reminders(&conn, &mut sinks, &utc("2030-01-01 09:00")).unwrap();
+
+    assert_eq!(std::fs::read_to_string(&out).unwrap(), "1 Pay rent\n");
+    std::fs::remove_file(&out).unwrap();
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn test_unix_socket_sink_writes_json_line() {
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixListener;
+
+    let conn = setup();
+    let path = std::env::temp_dir().join(format!("rust-to-do-list-{}-remind.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let mut sinks: Vec<Box<dyn ReminderSink>> = vec![Box::new(UnixSocketSink { path: path.to_string_lossy().to_string() })];
+
+    poll_reminders(&conn, &mut sinks, &utc("2030-01-01 09:00")).unwrap();
+
+    let (stream, _) = listener.accept().unwrap();
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).unwrap();
+    let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(value["name"], "Pay rent");
+    assert_eq!(value["remind_at"], utc("2030-01-01 08:00"));
+    std::fs::remove_file(&path).unwrap();
+  }
+}
@@ -0,0 +1,100 @@
+use std::io::{self, Write};
+
+use crate::commands::CommandOutcome;
+
+/// Somewhere the outcome of a command can be shown.
+pub trait Renderer {
+  fn render(&mut self, outcome: &CommandOutcome) -> io::Result<()>;
+}
+
+/// Human readable lines: the messages, then one line per task, on `out`,
+/// and the warnings on `err`.
+pub struct TextRenderer<O: Write, E: Write> {
+  pub out: O,
+  pub err: E,
+}
+
+impl TextRenderer<io::Stdout, io::Stderr> {
+  /// Render on the standard output and error of the process.
+  pub fn terminal() -> TextRenderer<io::Stdout, io::Stderr> {
+    TextRenderer { out: io::stdout(), err: io::stderr() }
+  }
+}
+
+impl<O: Write, E: Write> Renderer for TextRenderer<O, E> {
+  fn render(&mut self, outcome: &CommandOutcome) -> io::Result<()> {
+    for message in &outcome.messages {
+      writeln!(self.out, "{message}")?;
+    }
+    for task in &outcome.tasks {
+      writeln!(self.out, "{task}")?;
+    }
+    self.out.flush()?;
+    for warning in &outcome.warnings {
+      writeln!(self.err, "{warning}")?;
+    }
+
+    Ok(())
+  }
+}
+
+/// The whole outcome as one JSON document per line, for scripts.
+pub struct JsonRenderer<O: Write> {
+  pub out: O,
+}
+
+impl<O: Write> Renderer for JsonRenderer<O> {
+  fn render(&mut self, outcome: &CommandOutcome) -> io::Result<()> {
+    serde_json::to_writer(&mut self.out, outcome).map_err(io::Error::other)?;
+    writeln!(self.out)?;
+    self.out.flush()
+  }
+}
+
+/// The renderer of the command line, JSON when `--json` is given.
+pub fn terminal_renderer(json: bool) -> Box<dyn Renderer> {
+  match json {
+    true => Box::new(JsonRenderer { out: io::stdout() }),
+    false => Box::new(TextRenderer::terminal()),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::connect_db;
+  use crate::db::task::insert_task;
+
+  fn outcome() -> CommandOutcome {
+    let conn = connect_db(Some(true), None).unwrap();
+    CommandOutcome {
+      tasks: vec![insert_task(&conn, String::from("Buy milk")).unwrap()],
+      messages: vec![String::from("1 task(s) deleted successfully")],
+      warnings: vec![String::from("Error taking automatic backup")],
+    }
+  }
+
+  #[test]
+  fn test_text_renderer_splits_warnings() {
+    let mut renderer = TextRenderer { out: Vec::new(), err: Vec::new() };
+    renderer.render(&outcome()).unwrap();
+
+    let out = String::from_utf8(renderer.out).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], "1 task(s) deleted successfully");
+    assert!(lines[1].starts_with("id = 1, name = Buy milk, is_done = false"));
+    assert_eq!(String::from_utf8(renderer.err).unwrap(), "Error taking automatic backup\n");
+  }
+
+  #[test]
+  fn test_json_renderer_writes_one_document() {
+    let mut renderer = JsonRenderer { out: Vec::new() };
+    renderer.render(&outcome()).unwrap();
+
+    let value: serde_json::Value = serde_json::from_slice(&renderer.out).unwrap();
+    assert_eq!(value["tasks"][0]["name"], "Buy milk");
+    assert_eq!(value["messages"][0], "1 task(s) deleted successfully");
+    assert_eq!(value["warnings"].as_array().unwrap().len(), 1);
+  }
+}
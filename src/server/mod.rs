@@ -0,0 +1,286 @@
+use std::io::Read;
+
+use rusqlite::{Connection, Error};
+use serde::Deserialize;
+use serde_json::json;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::db::task::{delete_task, ensure_task_owner, insert_owned_task, read_owned_task, read_owned_tasks, update_task_status_by};
+use crate::db::with_transaction;
+use crate::db::user::{find_user_id_by_token, find_user_name};
+
+/// Largest request body accepted, in bytes.
+pub const MAX_BODY_SIZE: usize = 64 * 1024;
+
+#[derive(Deserialize)]
+struct NewTask {
+  name: String,
+}
+
+#[derive(Deserialize)]
+struct TaskStatus {
+  is_done: bool,
+}
+
+/// Status code and JSON body answered for a request.
+pub struct ApiResponse {
+  pub status: u16,
+  pub body: String,
+}
+
+impl ApiResponse {
+  fn json(status: u16, value: serde_json::Value) -> ApiResponse {
+    ApiResponse { status, body: value.to_string() }
+  }
+
+  fn error(status: u16, message: &str) -> ApiResponse {
+    ApiResponse::json(status, json!({ "error": message }))
+  }
+}
+
+fn db_error(err: Error) -> ApiResponse {
+  match err {
+    Error::QueryReturnedNoRows => ApiResponse::error(404, "Task not found"),
+    Error::InvalidParameterName(message) => ApiResponse::error(400, &message),
+    err => ApiResponse::error(500, &err.to_string()),
+  }
+}
+
+fn parse_id(segment: &str) -> Option<u32> {
+  segment.parse::<u32>().ok()
+}
+
+/// Resolve the `Authorization: Bearer <token>` header to a user before
+/// routing, so every handler only ever sees the caller's own tasks.
+pub fn handle_authenticated(conn: &Connection, authorization: Option<&str>, method: &str, url: &str, body: &str) -> ApiResponse {
+  let token = match authorization.and_then(|value| value.strip_prefix("Bearer ")) {
+    Some(token) => token.trim(),
+    None => return ApiResponse::error(401, "Missing bearer token"),
+  };
+
+  match find_user_id_by_token(conn, token) {
+    Ok(Some(owner_id)) => handle_request(conn, owner_id, method, url, body),
+    Ok(None) => ApiResponse::error(401, "Invalid token"),
+    Err(err) => db_error(err),
+  }
+}
+
+/// Route a single request from user `owner_id` to the matching `db::task` function.
+pub fn handle_request(conn: &Connection, owner_id: u32, method: &str, url: &str, body: &str) -> ApiResponse {
+  let path = url.split('?').next().unwrap_or("");
+  let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+  match (method, segments.as_slice()) {
+    ("GET", ["tasks"]) => match read_owned_tasks(conn, owner_id) {
+      Ok(tasks) => ApiResponse::json(200, json!(tasks)),
+      Err(err) => db_error(err),
+    },
+    ("GET", ["tasks", id]) => {
+      let Some(id) = parse_id(id) else {
+        return ApiResponse::error(400, "Invalid task id");
+      };
+      match read_owned_task(conn, id, owner_id) {
+        Ok(task) => ApiResponse::json(200, json!(task)),
+        Err(err) => db_error(err),
+      }
+    },
+    ("POST", ["tasks"]) => {
+      let new_task: NewTask = match serde_json::from_str(body) {
+        Ok(new_task) => new_task,
+        Err(err) => return ApiResponse::error(400, &err.to_string()),
+      };
+      match insert_owned_task(conn, new_task.name, owner_id) {
+        Ok(task) => ApiResponse::json(201, json!(task)),
+        Err(err) => db_error(err),
+      }
+    },
+    ("PATCH", ["tasks", id]) => {
+      let Some(id) = parse_id(id) else {
+        return ApiResponse::error(400, "Invalid task id");
+      };
+      let status: TaskStatus = match serde_json::from_str(body) {
+        Ok(status) => status,
+        Err(err) => return ApiResponse::error(400, &err.to_string()),
+      };
+      // The owner check and the change are one transaction, so the task cannot change hands in between
+      let updated = with_transaction(conn, |conn| {
+        ensure_task_owner(conn, id, owner_id)?;
+        let completed_by = find_user_name(conn, owner_id).ok();
+        update_task_status_by(conn, id, status.is_done, completed_by.as_deref())
+      });
+      match updated {
+        Ok(task) => ApiResponse::json(200, json!(task)),
+        Err(err) => db_error(err),
+      }
+    },
+    ("DELETE", ["tasks", id]) => {
+      let Some(id) = parse_id(id) else {
+        return ApiResponse::error(400, "Invalid task id");
+      };
+      let deleted = with_transaction(conn, |conn| {
+        ensure_task_owner(conn, id, owner_id)?;
+        delete_task(conn, id)
+      });
+      match deleted {
+        Ok(task) => ApiResponse::json(200, json!(task)),
+        Err(err) => db_error(err),
+      }
+    },
+    (_, ["tasks"]) | (_, ["tasks", _]) => ApiResponse::error(405, "Method not allowed"),
+    _ => ApiResponse::error(404, "Not found"),
+  }
+}
+
+/// Read the body of `request`, refusing it with a 413 once it is larger
+/// than `MAX_BODY_SIZE`, whether it announces its length or not.
+fn read_body(request: &mut Request) -> Result<String, ApiResponse> {
+  let too_large = || ApiResponse::error(413, &format!("Request body is larger than {MAX_BODY_SIZE} bytes"));
+  if request.body_length().is_some_and(|length| length > MAX_BODY_SIZE) {
+    return Err(too_large());
+  }
+
+  let mut body = String::new();
+  request.as_reader().take(MAX_BODY_SIZE as u64 + 1).read_to_string(&mut body)
+    .map_err(|err| ApiResponse::error(400, &err.to_string()))?;
+  if body.len() > MAX_BODY_SIZE {
+    return Err(too_large());
+  }
+
+  Ok(body)
+}
+
+/// Serve the tasks REST API on `bind` until the process is stopped.
+pub fn serve(conn: &Connection, bind: &str) -> Result<(), String> {
+  let server = Server::http(bind).map_err(|err| err.to_string())?;
+  let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+
+  for mut request in server.incoming_requests() {
+    let response = match read_body(&mut request) {
+      Ok(body) => {
+        let method = match request.method() {
+          Method::Get => "GET",
+          Method::Post => "POST",
+          Method::Patch => "PATCH",
+          Method::Delete => "DELETE",
+          _ => "OTHER",
+        };
+        let authorization = request.headers().iter()
+          .find(|header| header.field.equiv("Authorization"))
+          .map(|header| header.value.as_str().to_string());
+        handle_authenticated(conn, authorization.as_deref(), method, request.url(), &body)
+      },
+      Err(response) => response,
+    };
+
+    let reply = Response::from_string(response.body)
+      .with_status_code(response.status)
+      .with_header(content_type.clone());
+    if let Err(err) = request.respond(reply) {
+      log::warn!("Error answering request: {}", err);
+    }
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::connect_db;
+  use crate::db::task::insert_owned_task;
+  use crate::db::user::insert_user;
+
+  fn setup() -> (Connection, u32) {
+    // Set up test environment with one user
+    let conn = connect_db(Some(true), None).unwrap();
+    let (user, _) = insert_user(&conn, "alice".to_string()).unwrap();
+    (conn, user.id)
+  }
+
+  #[test]
+  fn test_post_and_get_tasks() {
+    let (conn, owner) = setup();
+
+    let response = handle_request(&conn, owner, "POST", "/tasks", r#"{"name": "Api task"}"#);
+    assert_eq!(response.status, 201);
+    assert!(response.body.contains("Api task"));
+
+    let response = handle_request(&conn, owner, "GET", "/tasks", "");
+    assert_eq!(response.status, 200);
+    let tasks: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(tasks.as_array().unwrap().len(), 1);
+  }
+
+  #[test]
+  fn test_post_rejects_empty_name_and_bad_json() {
+    let (conn, owner) = setup();
+
+    assert_eq!(handle_request(&conn, owner, "POST", "/tasks", r#"{"name": ""}"#).status, 400);
+    assert_eq!(handle_request(&conn, owner, "POST", "/tasks", "not json").status, 400);
+  }
+
+  #[test]
+  fn test_patch_and_delete_missing_task_return_not_found() {
+    let (conn, owner) = setup();
+
+    assert_eq!(handle_request(&conn, owner, "PATCH", "/tasks/42", r#"{"is_done": true}"#).status, 404);
+    assert_eq!(handle_request(&conn, owner, "DELETE", "/tasks/42", "").status, 404);
+  }
+
+  #[test]
+  fn test_get_single_task() {
+    let (conn, owner) = setup();
+    let task = insert_owned_task(&conn, "Api task".to_string(), owner).unwrap();
+
+    let response = handle_request(&conn, owner, "GET", &format!("/tasks/{}", task.id), "");
+    assert_eq!(response.status, 200);
+    assert!(response.body.contains("Api task"));
+    assert_eq!(handle_request(&conn, owner, "GET", "/tasks/42", "").status, 404);
+    assert_eq!(handle_request(&conn, owner, "GET", "/tasks/abc", "").status, 400);
+  }
+
+  #[test]
+  fn test_patch_updates_status() {
+    let (conn, owner) = setup();
+    let task = insert_owned_task(&conn, "Api task".to_string(), owner).unwrap();
+
+    let response = handle_request(&conn, owner, "PATCH", &format!("/tasks/{}", task.id), r#"{"is_done": true}"#);
+    assert_eq!(response.status, 200);
+    assert!(response.body.contains(r#""is_done":true"#));
+  }
+
+  #[test]
+  fn test_unknown_routes() {
+    let (conn, owner) = setup();
+
+    assert_eq!(handle_request(&conn, owner, "GET", "/nothing", "").status, 404);
+    assert_eq!(handle_request(&conn, owner, "PUT", "/tasks", "").status, 405);
+    assert_eq!(handle_request(&conn, owner, "DELETE", "/tasks/abc", "").status, 400);
+  }
+
+  #[test]
+  fn test_requests_without_valid_token_are_rejected() {
+    let (conn, _) = setup();
+
+    assert_eq!(handle_authenticated(&conn, None, "GET", "/tasks", "").status, 401);
+    assert_eq!(handle_authenticated(&conn, Some("Bearer nope"), "GET", "/tasks", "").status, 401);
+    assert_eq!(handle_authenticated(&conn, Some("Basic abc"), "GET", "/tasks", "").status, 401);
+  }
+
+  #[test]
+  fn test_users_cannot_touch_each_other_tasks() {
+    let (conn, alice) = setup();
+    let (_, bob_token) = insert_user(&conn, "bob".to_string()).unwrap();
+    let task = insert_owned_task(&conn, "Alice task".to_string(), alice).unwrap();
+    let bob_auth = format!("Bearer {bob_token}");
+
+    let response = handle_authenticated(&conn, Some(&bob_auth), "GET", "/tasks", "");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.body, "[]");
+
+    let path = format!("/tasks/{}", task.id);
+    assert_eq!(handle_authenticated(&conn, Some(&bob_auth), "GET", &path, "").status, 404);
+    assert_eq!(handle_authenticated(&conn, Some(&bob_auth), "PATCH", &path, r#"{"is_done": true}"#).status, 404);
+    assert_eq!(handle_authenticated(&conn, Some(&bob_auth), "DELETE", &path, "").status, 404);
+  }
+}
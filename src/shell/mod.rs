@@ -0,0 +1,217 @@
+use std::env;
+use std::iter;
+use std::path::PathBuf;
+
+use clap::{CommandFactory, Parser};
+use rusqlite::Connection;
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{Context, Editor, Helper, Highlighter, Hinter, Validator};
+
+use crate::commands::{command_switch, CommandError, Commands};
+use crate::render::terminal_renderer;
+use crate::db::task::read_tasks;
+use crate::Args;
+
+/// File, in the home directory, where the shell history is kept between sessions.
+pub const HISTORY_FILE: &str = ".rust_to_do_list_history";
+
+/// Completes subcommand names and live task ids, using a snapshot of the
+/// tasks that is refreshed after every command.
+#[derive(Helper, Highlighter, Hinter, Validator)]
+pub struct ShellHelper {
+  subcommands: Vec<String>,
+  tasks: Vec<(u32, String)>,
+}
+
+impl ShellHelper {
+  pub fn new(conn: &Connection) -> ShellHelper {
+    let subcommands = Args::command()
+      .get_subcommands()
+      .filter(|command| !command.is_hide_set())
+      .map(|command| command.get_name().to_string())
+      .chain(["exit".to_string()])
+      .collect();
+    let mut helper = ShellHelper { subcommands, tasks: Vec::new() };
+    helper.refresh(conn);
+    helper
+  }
+
+  pub fn refresh(&mut self, conn: &Connection) {
+    self.tasks = read_tasks(conn)
+      .map(|tasks| tasks.into_iter().map(|task| (task.id, task.name)).collect())
+      .unwrap_or_default();
+  }
+
+  /// Candidates for the word ending at the end of `line`.
+  pub fn complete_line(&self, line: &str) -> (usize, Vec<Pair>) {
+    let start = line.rfind(char::is_whitespace).map(|index| index + 1).unwrap_or(0);
+    let prefix = &line[start..];
+    let previous: Vec<&str> = line[..start].split_whitespace().collect();
+
+    let candidates = match previous.as_slice() {
+      [] => self.subcommands.iter()
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| Pair { display: name.clone(), replacement: format!("{name} ") })
+        .collect(),
+      [.., "--id" | "-i"] => self.complete_ids(prefix),
+      ["delete" | "done" | "reopen" | "start" | "time", ..] if !prefix.starts_with('-') => self.complete_ids(prefix),
+      _ => Vec::new(),
+    };
+
+    (start, candidates)
+  }
+
+  /// Match ids by prefix or names by substring, always inserting the id.
+  fn complete_ids(&self, prefix: &str) -> Vec<Pair> {
+    let needle = prefix.to_lowercase();
+    self.tasks.iter()
+      .filter(|(id, name)| id.to_string().starts_with(prefix) || name.to_lowercase().contains(&needle))
+      .map(|(id, name)| Pair { display: format!("{id}  {name}"), replacement: format!("{id} ") })
+      .collect()
+  }
+}
+
+impl Completer for ShellHelper {
+  type Candidate = Pair;
+
+  fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<Pair>)> {
+    Ok(self.complete_line(&line[..pos]))
+  }
+}
+
+/// Parse a shell line with the same grammar as the binary, minus its name.
+pub fn parse_line(line: &str) -> Result<Args, String> {
+  let words = shlex::split(line).ok_or_else(|| "Unbalanced quotes".to_string())?;
+  Args::try_parse_from(iter::once("todo".to_string()).chain(words))
+    .map_err(|err| err.render().to_string())
+}
+
+fn history_path() -> PathBuf {
+  match env::var_os("HOME") {
+    Some(home) => PathBuf::from(home).join(HISTORY_FILE),
+    None => PathBuf::from(HISTORY_FILE),
+  }
+}
+
+/// Read and run commands against `conn` until `exit` or end of input.
+pub fn run_shell(conn: &Connection) -> rustyline::Result<()> {
+  let mut editor: Editor<ShellHelper, DefaultHistory> = Editor::new()?;
+  editor.set_helper(Some(ShellHelper::new(conn)));
+  let history = history_path();
+  let _ = editor.load_history(&history);
+
+  loop {
+    let line = match editor.readline("todo> ") {
+      Ok(line) => line,
+      Err(ReadlineError::Interrupted) => continue,
+      Err(ReadlineError::Eof) => break,
+      Err(err) => return Err(err),
+    };
+    let line = line.trim();
+    if line.is_empty() {
+      continue;
+    }
+    editor.add_history_entry(line)?;
+    if line == "exit" || line == "quit" {
+      break;
+    }
+
+    match parse_line(line) {
+      Ok(Args { cmd: Commands::Shell, .. }) => log::warn!("Already in the shell"),
+      Ok(args) => {
+        let json = args.options.json;
+        let rendered = command_switch(args, conn).and_then(|outcome| {
+          terminal_renderer(json).render(&outcome).map_err(|err| CommandError::Storage(err.to_string()))
+        });
+        if let Err(message) = rendered {
+          eprintln!("{message}");
+        }
+      },
+      Err(message) => eprint!("{message}"),
+    }
+
+    if let Some(helper) = editor.helper_mut() {
+      helper.refresh(conn);
+    }
+  }
+
+  editor.save_history(&history)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::connect_db;
+  use crate::db::task::insert_task;
+
+  fn setup() -> Connection {
+    // Set up test environment
+    let conn = connect_db(Some(true), None).unwrap();
+    insert_task(&conn, "Buy milk".to_string()).unwrap();
+    insert_task(&conn, "Write report".to_string()).unwrap();
+    conn
+  }
+
+  fn replacements(candidates: Vec<Pair>) -> Vec<String> {
+    candidates.into_iter().map(|pair| pair.replacement).collect()
+  }
+
+  #[test]
+  fn test_parse_line_uses_command_grammar() {
+    let args = parse_line("add --name \"Buy milk\"").unwrap();
+
+    match args.cmd {
+      Commands::Add { name, .. } => {
+        assert_eq!(name, "Buy milk");
+        assert!(!args.options.dry_test);
+      }
+      _ => panic!("Expected an add command"),
+    }
+  }
+
+  #[test]
+  fn test_parse_line_reports_errors() {
+    assert!(parse_line("frobnicate").is_err());
+    assert!(parse_line("add --name \"unterminated").is_err());
+  }
+
+  #[test]
+  fn test_complete_subcommands() {
+    let conn = setup();
+    let helper = ShellHelper::new(&conn);
+
+    let (start, candidates) = helper.complete_line("up");
+    assert_eq!(start, 0);
+    assert_eq!(replacements(candidates), vec!["update "]);
+  }
+
+  #[test]
+  fn test_complete_task_ids_and_names() {
+    let conn = setup();
+    let helper = ShellHelper::new(&conn);
+
+    let (start, candidates) = helper.complete_line("update --id ");
+    assert_eq!(start, 12);
+    assert_eq!(replacements(candidates), vec!["1 ", "2 "]);
+
+    let (_, candidates) = helper.complete_line("delete rep");
+    assert_eq!(replacements(candidates), vec!["2 "]);
+
+    let (_, candidates) = helper.complete_line("add --name ");
+    assert!(candidates.is_empty());
+  }
+
+  #[test]
+  fn test_refresh_picks_up_new_tasks() {
+    let conn = setup();
+    let mut helper = ShellHelper::new(&conn);
+
+    insert_task(&conn, "Third".to_string()).unwrap();
+    helper.refresh(&conn);
+
+    let (_, candidates) = helper.complete_line("delete ");
+    assert_eq!(candidates.len(), 3);
+  }
+}
@@ -0,0 +1,213 @@
+use std::error;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rusqlite::Connection;
+
+use crate::db::selection::TaskFilter;
+use crate::db::store::{StoreError, TaskStore};
+use crate::db::task::Task;
+use crate::db::{build_db, configure_connection, trace_sql, BUSY_TIMEOUT};
+
+/// Why a `TodoList` operation failed.
+#[derive(Debug)]
+pub enum TodoError {
+  /// There is no task with this id, or it is not in the state the operation
+  /// needs, e.g. restoring a task that was never deleted.
+  NotFound(u32),
+  /// The input was rejected, e.g. an empty task name.
+  Invalid(String),
+  /// The storage could not be opened, read or written.
+  Storage(StorageError),
+}
+
+impl fmt::Display for TodoError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      TodoError::NotFound(id) => write!(f, "Task {id} not found"),
+      TodoError::Invalid(message) => write!(f, "{message}"),
+      TodoError::Storage(err) => write!(f, "Storage error: {err}"),
+    }
+  }
+}
+
+impl error::Error for TodoError {
+  fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+    match self {
+      TodoError::Storage(err) => Some(err),
+      _ => None,
+    }
+  }
+}
+
+impl From<StoreError> for TodoError {
+  fn from(err: StoreError) -> TodoError {
+    match err {
+      StoreError::Invalid(message) => TodoError::Invalid(message),
+      err => TodoError::Storage(StorageError(err)),
+    }
+  }
+}
+
+/// A failure of the storage behind a `TodoList`, such as a database that
+/// cannot be opened; its `source` tells what the storage reported.
+#[derive(Debug)]
+pub struct StorageError(StoreError);
+
+impl fmt::Display for StorageError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl error::Error for StorageError {
+  fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+    Some(&self.0)
+  }
+}
+
+/// Report a missing task as the task `id` not being found.
+fn task_error(id: u32) -> impl Fn(StoreError) -> TodoError {
+  move |err| match err {
+    StoreError::NotFound => TodoError::NotFound(id),
+    err => TodoError::from(err),
+  }
+}
+
+fn open_connection(path: Option<&Path>, busy_timeout: Duration) -> rusqlite::Result<Connection> {
+  let mut conn = match path {
+    Some(path) => Connection::open(path)?,
+    None => Connection::open_in_memory()?,
+  };
+  configure_connection(&conn)?;
+  conn.busy_timeout(busy_timeout)?;
+  trace_sql(&mut conn);
+  build_db(&conn)?;
+
+  Ok(conn)
+}
+
+/// Options for opening a `TodoList`, from `TodoList::builder`.
+pub struct TodoListBuilder {
+  store: Option<Box<dyn TaskStore>>,
+  path: Option<PathBuf>,
+  busy_timeout: Duration,
+  completed_by: Option<String>,
+}
+
+impl TodoListBuilder {
+  /// Database file to open, created if needed.
+  pub fn path(mut self, path: impl AsRef<Path>) -> TodoListBuilder {
+    self.path = Some(path.as_ref().to_path_buf());
+    self
+  }
+
+  /// Keep the tasks in memory only, e.g. in tests.
+  pub fn in_memory(mut self) -> TodoListBuilder {
+    self.path = None;
+    self
+  }
+
+  /// Keep the tasks in `store`, e.g. a `MemoryStore` or a `JsonStore`,
+  /// instead of a SQLite database.
+  pub fn store(mut self, store: impl TaskStore) -> TodoListBuilder {
+    self.store = Some(Box::new(store));
+    self
+  }
+
+  /// How long to wait for other processes using the same file.
+  pub fn busy_timeout(mut self, timeout: Duration) -> TodoListBuilder {
+    self.busy_timeout = timeout;
+    self
+  }
+
+  /// Name recorded as `completed_by` when completing tasks.
+  pub fn completed_by(mut self, name: impl Into<String>) -> TodoListBuilder {
+    self.completed_by = Some(name.into());
+    self
+  }
+
+  /// Open the database, migrating it to the current schema, unless a
+  /// `store` was given.
+  pub fn build(self) -> Result<TodoList, TodoError> {
+    let store = match self.store {
+      Some(store) => store,
+      None => Box::new(open_connection(self.path.as_deref(), self.busy_timeout).map_err(StoreError::from)?),
+    };
+
+    Ok(TodoList { store, completed_by: self.completed_by })
+  }
+}
+
+/// A to-do list stored in a SQLite database, the same one the command line
+/// uses, or in any other `TaskStore`. Every method returns owned values and
+/// is atomic.
+///
+/// ```
+/// use rust_to_do_list::{TaskFilter, TodoList};
+///
+/// let list = TodoList::builder().in_memory().build().unwrap();
+/// let task = list.add("Buy milk").unwrap();
+/// list.complete(task.id).unwrap();
+///
+/// let done = TaskFilter { is_done: Some(true), ..TaskFilter::default() };
+/// assert_eq!(list.list(&done).unwrap().len(), 1);
+/// ```
+pub struct TodoList {
+  store: Box<dyn TaskStore>,
+  completed_by: Option<String>,
+}
+
+impl TodoList {
+  /// Open the database file at `path` with the default options.
+  pub fn open(path: impl AsRef<Path>) -> Result<TodoList, TodoError> {
+    TodoList::builder().path(path).build()
+  }
+
+  pub fn builder() -> TodoListBuilder {
+    TodoListBuilder { store: None, path: None, busy_timeout: BUSY_TIMEOUT, completed_by: None }
+  }
+
+  /// Add an open task named `name`.
+  pub fn add(&self, name: impl Into<String>) -> Result<Task, TodoError> {
+    Ok(self.store.insert(name.into())?)
+  }
+
+  /// The task `id`, deleted or not.
+  pub fn get(&self, id: u32) -> Result<Task, TodoError> {
+    self.store.get(id).map_err(task_error(id))
+  }
+
+  /// Live tasks matching `filter`, oldest first; `TaskFilter::default()`
+  /// matches all of them.
+  pub fn list(&self, filter: &TaskFilter) -> Result<Vec<Task>, TodoError> {
+    Ok(self.store.list()?.into_iter().filter(|task| filter.matches(task)).collect())
+  }
+
+  /// Mark the live task `id` as done.
+  pub fn complete(&self, id: u32) -> Result<Task, TodoError> {
+    self.set_status(id, true)
+  }
+
+  /// Mark the live task `id` as open again.
+  pub fn reopen(&self, id: u32) -> Result<Task, TodoError> {
+    self.set_status(id, false)
+  }
+
+  fn set_status(&self, id: u32, is_done: bool) -> Result<Task, TodoError> {
+    let tasks = self.store.update(&[id], is_done, self.completed_by.as_deref()).map_err(task_error(id))?;
+    tasks.into_iter().next().ok_or(TodoError::NotFound(id))
+  }
+
+  /// Soft-delete the live task `id`; it can be brought back with `restore`.
+  pub fn delete(&self, id: u32) -> Result<Task, TodoError> {
+    let tasks = self.store.delete(&[id]).map_err(task_error(id))?;
+    tasks.into_iter().next().ok_or(TodoError::NotFound(id))
+  }
+
+  /// Bring back the deleted task `id`.
+  pub fn restore(&self, id: u32) -> Result<Task, TodoError> {
+    self.store.restore(id).map_err(task_error(id))
+  }
+}
@@ -0,0 +1,339 @@
+use std::io::{self, Write};
+use std::panic;
+use std::sync::Arc;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, execute, queue};
+use rusqlite::{Connection, Result};
+
+use crate::db::task::{delete_task, insert_task, read_tasks, update_task_status, Task};
+
+#[derive(Debug, PartialEq)]
+pub enum Mode {
+  Normal,
+  Adding,
+  Filtering,
+}
+
+/// Everything the list view needs to draw itself, kept apart from the
+/// terminal so key handling can be tested without a tty.
+pub struct TuiState {
+  pub tasks: Vec<Task>,
+  pub selected: usize,
+  pub filter: String,
+  pub input: String,
+  pub mode: Mode,
+  pub status: String,
+  pub quit: bool,
+}
+
+impl TuiState {
+  pub fn new(conn: &Connection) -> Result<TuiState> {
+    let mut state = TuiState {
+      tasks: Vec::new(),
+      selected: 0,
+      filter: String::new(),
+      input: String::new(),
+      mode: Mode::Normal,
+      status: String::new(),
+      quit: false,
+    };
+    state.reload(conn)?;
+    Ok(state)
+  }
+
+  /// Re-read the tasks from the database after any change.
+  pub fn reload(&mut self, conn: &Connection) -> Result<()> {
+    self.tasks = read_tasks(conn)?;
+    let visible = self.visible().len();
+    if self.selected >= visible {
+      self.selected = visible.saturating_sub(1);
+    }
+    Ok(())
+  }
+
+  /// Tasks matching the current filter, case-insensitively.
+  pub fn visible(&self) -> Vec<&Task> {
+    let filter = self.filter.to_lowercase();
+    self.tasks.iter()
+      .filter(|task| task.name.to_lowercase().contains(&filter))
+      .collect()
+  }
+
+  fn selected_id(&self) -> Option<u32> {
+    self.visible().get(self.selected).map(|task| task.id)
+  }
+
+  pub fn handle_key(&mut self, conn: &Connection, key: KeyEvent) -> Result<()> {
+    match self.mode {
+      Mode::Normal => self.handle_normal_key(conn, key),
+      Mode::Adding => self.handle_add_key(conn, key),
+      Mode::Filtering => {
+        self.handle_filter_key(key);
+        Ok(())
+      },
+    }
+  }
+
+  fn handle_normal_key(&mut self, conn: &Connection, key: KeyEvent) -> Result<()> {
+    match key.code {
+      KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+      KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => self.quit = true,
+      KeyCode::Up | KeyCode::Char('k') => self.selected = self.selected.saturating_sub(1),
+      KeyCode::Down | KeyCode::Char('j') if self.selected + 1 < self.visible().len() => self.selected += 1,
+      KeyCode::Char(' ') => {
+        if let Some(id) = self.selected_id() {
+          let is_done = self.tasks.iter().any(|task| task.id == id && task.is_done);
+          let task = update_task_status(conn, id, !is_done)?;
+          self.status = format!("Task {} marked as {}", task.id, if task.is_done { "done" } else { "not done" });
+          self.reload(conn)?;
+        }
+      },
+      KeyCode::Char('d') => {
+        if let Some(id) = self.selected_id() {
+          delete_task(conn, id)?;
+          self.status = format!("Task {id} deleted");
+          self.reload(conn)?;
+        }
+      },
+      KeyCode::Char('a') => {
+        self.mode = Mode::Adding;
+        self.input.clear();
+      },
+      KeyCode::Char('/') => self.mode = Mode::Filtering,
+      _ => {},
+    }
+    Ok(())
+  }
+
+  fn handle_add_key(&mut self, conn: &Connection, key: KeyEvent) -> Result<()> {
+    match key.code {
+      KeyCode::Enter => {
+        let name = std::mem::take(&mut self.input);
+        self.mode = Mode::Normal;
+        match insert_task(conn, name) {
+          Ok(task) => self.status = format!("Task {} added", task.id),
+          Err(err) => self.status = err.to_string(),
+        }
+        self.reload(conn)?;
+      },
+      KeyCode::Esc => {
+        self.input.clear();
+        self.mode = Mode::Normal;
+      },
+      KeyCode::Backspace => {
+        self.input.pop();
+      },
+      KeyCode::Char(c) => self.input.push(c),
+      _ => {},
+    }
+    Ok(())
+  }
+
+  fn handle_filter_key(&mut self, key: KeyEvent) {
+    match key.code {
+      KeyCode::Enter => self.mode = Mode::Normal,
+      KeyCode::Esc => {
+        self.filter.clear();
+        self.mode = Mode::Normal;
+      },
+      KeyCode::Backspace => {
+        self.filter.pop();
+      },
+      KeyCode::Char(c) => self.filter.push(c),
+      _ => {},
+    }
+    self.selected = 0;
+  }
+}
+
+fn restore_terminal() {
+  let _ = terminal::disable_raw_mode();
+  let _ = execute!(io::stdout(), LeaveAlternateScreen, cursor::Show);
+}
+
+/// Leaves the alternate screen when dropped, also on early returns.
+struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+  fn drop(&mut self) {
+    restore_terminal();
+  }
+}
+
+fn draw(out: &mut impl Write, state: &TuiState) -> io::Result<()> {
+  let (_, rows) = terminal::size().unwrap_or((80, 24));
+  queue!(out, Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+  queue!(out, Print("To-do list  [space] toggle  [a] add  [d] delete  [/] filter  [q] quit"))?;
+
+  let visible = state.visible();
+  let list_rows = rows.saturating_sub(4) as usize;
+  let offset = state.selected.saturating_sub(list_rows.saturating_sub(1));
+  for (line, (index, task)) in visible.iter().enumerate().skip(offset).take(list_rows).enumerate() {
+    queue!(out, cursor::MoveTo(0, line as u16 + 2))?;
+    if index == state.selected {
+      queue!(out, SetAttribute(Attribute::Reverse))?;
+    }
+    let check = if task.is_done { "x" } else { " " };
+    queue!(out, Print(format!("[{check}] {:>4}  {}", task.id, task.name)), SetAttribute(Attribute::Reset))?;
+  }
+  if visible.is_empty() {
+    queue!(out, cursor::MoveTo(0, 2), Print("No tasks"))?;
+  }
+
+  queue!(out, cursor::MoveTo(0, rows.saturating_sub(1)))?;
+  match state.mode {
+    Mode::Adding => queue!(out, Print(format!("New task: {}", state.input)))?,
+    Mode::Filtering => queue!(out, Print(format!("/{}", state.filter)))?,
+    Mode::Normal if !state.filter.is_empty() => queue!(out, Print(format!("filter: {}  {}", state.filter, state.status)))?,
+    Mode::Normal => queue!(out, Print(&state.status))?,
+  }
+
+  out.flush()
+}
+
+/// Run the full-screen list view until the user quits.
+pub fn run_tui(conn: &Connection) -> io::Result<()> {
+  let mut state = TuiState::new(conn).map_err(io::Error::other)?;
+
+  // Leave the terminal usable if drawing panics, then put the hook that was
+  // there before back
+  let previous_hook = Arc::new(panic::take_hook());
+  let hook = Arc::clone(&previous_hook);
+  panic::set_hook(Box::new(move |info| {
+    restore_terminal();
+    hook(info);
+  }));
+
+  let result = event_loop(conn, &mut state);
+
+  drop(panic::take_hook());
+  if let Ok(hook) = Arc::try_unwrap(previous_hook) {
+    panic::set_hook(hook);
+  }
+  result
+}
+
+fn event_loop(conn: &Connection, state: &mut TuiState) -> io::Result<()> {
+  terminal::enable_raw_mode()?;
+  let _guard = TerminalGuard;
+  let mut out = io::stdout();
+  execute!(out, EnterAlternateScreen, cursor::Hide)?;
+
+  while !state.quit {
+    draw(&mut out, state)?;
+    if let Event::Key(key) = event::read()? {
+      if key.kind != KeyEventKind::Press {
+        continue;
+      }
+      if let Err(err) = state.handle_key(conn, key) {
+        state.status = err.to_string();
+      }
+    }
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::connect_db;
+
+  fn setup() -> Connection {
+    // Set up test environment
+    let conn = connect_db(Some(true), None).unwrap();
+    insert_task(&conn, "First task".to_string()).unwrap();
+    insert_task(&conn, "Second task".to_string()).unwrap();
+    conn
+  }
+
+  fn press(state: &mut TuiState, conn: &Connection, code: KeyCode) {
+    state.handle_key(conn, KeyEvent::new(code, KeyModifiers::NONE)).unwrap();
+  }
+
+  #[test]
+  fn test_navigation_stays_in_bounds() {
+    let conn = setup();
+    let mut state = TuiState::new(&conn).unwrap();
+
+    press(&mut state, &conn, KeyCode::Up);
+    assert_eq!(state.selected, 0);
+    press(&mut state, &conn, KeyCode::Down);
+    press(&mut state, &conn, KeyCode::Down);
+    assert_eq!(state.selected, 1);
+  }
+
+  #[test]
+  fn test_space_toggles_selected_task() {
+    let conn = setup();
+    let mut state = TuiState::new(&conn).unwrap();
+
+    press(&mut state, &conn, KeyCode::Char(' '));
+    assert!(read_tasks(&conn).unwrap()[0].is_done);
+
+    press(&mut state, &conn, KeyCode::Char(' '));
+    assert!(!read_tasks(&conn).unwrap()[0].is_done);
+  }
+
+  #[test]
+  fn test_add_task_through_input() {
+    let conn = setup();
+    let mut state = TuiState::new(&conn).unwrap();
+
+    press(&mut state, &conn, KeyCode::Char('a'));
+    for c in "New".chars() {
+      press(&mut state, &conn, KeyCode::Char(c));
+    }
+    press(&mut state, &conn, KeyCode::Enter);
+
+    assert_eq!(state.mode, Mode::Normal);
+    assert_eq!(state.tasks.len(), 3);
+    assert_eq!(state.tasks[2].name, "New");
+  }
+
+  #[test]
+  fn test_delete_selected_task() {
+    let conn = setup();
+    let mut state = TuiState::new(&conn).unwrap();
+
+    press(&mut state, &conn, KeyCode::Down);
+    press(&mut state, &conn, KeyCode::Char('d'));
+
+    let tasks = read_tasks(&conn).unwrap();
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0].name, "First task");
+    assert_eq!(state.selected, 0);
+  }
+
+  #[test]
+  fn test_filter_limits_visible_tasks() {
+    let conn = setup();
+    let mut state = TuiState::new(&conn).unwrap();
+
+    press(&mut state, &conn, KeyCode::Char('/'));
+    for c in "sec".chars() {
+      press(&mut state, &conn, KeyCode::Char(c));
+    }
+    press(&mut state, &conn, KeyCode::Enter);
+
+    let visible = state.visible();
+    assert_eq!(visible.len(), 1);
+    assert_eq!(visible[0].name, "Second task");
+
+    press(&mut state, &conn, KeyCode::Char('/'));
+    press(&mut state, &conn, KeyCode::Esc);
+    assert_eq!(state.visible().len(), 2);
+  }
+
+  #[test]
+  fn test_quit_key() {
+    let conn = setup();
+    let mut state = TuiState::new(&conn).unwrap();
+
+    press(&mut state, &conn, KeyCode::Char('q'));
+    assert!(state.quit);
+  }
+}
@@ -0,0 +1,68 @@
+use std::fs;
+
+use rust_to_do_list::{MemoryStore, TaskFilter, TodoError, TodoList};
+
+#[test]
+fn test_api_add_complete_delete_restore() {
+    let list = TodoList::builder().in_memory().completed_by("ana").build().unwrap();
+    let milk = list.add("Buy milk").unwrap();
+    let report = list.add("Write report").unwrap();
+
+    let done = list.complete(milk.id).unwrap();
+    assert!(done.is_done);
+    assert_eq!(done.completed_by.as_deref(), Some("ana"));
+
+    let open = TaskFilter { is_done: Some(false), ..TaskFilter::default() };
+    let names: Vec<String> = list.list(&open).unwrap().into_iter().map(|task| task.name).collect();
+    assert_eq!(names, vec!["Write report"]);
+
+    let deleted = list.delete(report.id).unwrap();
+    assert!(deleted.deleted_at.is_some());
+    assert_eq!(list.list(&TaskFilter::default()).unwrap().len(), 1);
+
+    list.restore(report.id).unwrap();
+    assert_eq!(list.list(&TaskFilter::default()).unwrap().len(), 2);
+    assert!(!list.reopen(milk.id).unwrap().is_done);
+}
+
+#[test]
+fn test_api_typed_errors() {
+    let list = TodoList::builder().in_memory().build().unwrap();
+    let task = list.add("Task").unwrap();
+
+    assert!(matches!(list.add(""), Err(TodoError::Invalid(_))));
+    assert!(matches!(list.complete(999), Err(TodoError::NotFound(999))));
+    assert!(matches!(list.restore(task.id), Err(TodoError::NotFound(id)) if id == task.id));
+
+    list.delete(task.id).unwrap();
+    assert!(matches!(list.complete(task.id), Err(TodoError::NotFound(_))), "Deleted tasks cannot be completed");
+    assert_eq!(list.delete(task.id).unwrap_err().to_string(), format!("Task {} not found", task.id));
+}
+
+#[test]
+fn test_api_on_another_store() {
+    let list = TodoList::builder().store(MemoryStore::new()).completed_by("ana").build().unwrap();
+    let task = list.add("Buy milk").unwrap();
+
+    assert_eq!(list.complete(task.id).unwrap().completed_by.as_deref(), Some("ana"));
+    list.delete(task.id).unwrap();
+    assert!(list.list(&TaskFilter::default()).unwrap().is_empty());
+    assert!(matches!(list.complete(task.id), Err(TodoError::NotFound(id)) if id == task.id));
+    assert_eq!(list.restore(task.id).unwrap().name, "Buy milk");
+    assert!(matches!(list.add(""), Err(TodoError::Invalid(_))));
+}
+
+#[test]
+fn test_api_open_file_keeps_tasks() {
+    let path = std::env::temp_dir().join(format!("rust-to-do-list-{}-api.db", std::process::id()));
+    let _ = fs::remove_file(&path);
+
+    let id = TodoList::open(&path).unwrap().add("Persisted").unwrap().id;
+    let reopened = TodoList::open(&path).unwrap();
+    assert_eq!(reopened.get(id).unwrap().name, "Persisted");
+
+    drop(reopened);
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = fs::remove_file(format!("{}{suffix}", path.display()));
+    }
+}
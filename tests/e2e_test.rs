@@ -28,7 +28,7 @@ fn test_cli_add() {
         .expect("Error adding task");
 
     assert!(output.status.success());
-    assert!(String::from_utf8_lossy(&output.stdout).contains("Add task"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Add task"));
     assert!(String::from_utf8_lossy(&output.stdout).contains("Test"));
 
     delete_test_db(); // Tear Down
@@ -46,7 +46,7 @@ fn test_cli_read() {
         .expect("Error reading tasks");
 
     assert!(output.status.success());
-    assert!(String::from_utf8_lossy(&output.stdout).contains("List tasks"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("List tasks"));
     assert!(!String::from_utf8_lossy(&output.stdout).contains("1"));
 
     delete_test_db(); // Tear Down
@@ -67,7 +67,8 @@ fn test_cli_no_task_update_error() {
         .output()
         .expect("Error updating task");
 
-    assert!(!output.status.success());
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Task not found"));
 
     delete_test_db(); // Tear Down
 }
@@ -84,8 +85,69 @@ fn test_cli_no_task_delete_error() {
         .output()
         .expect("Error deleting task");
 
-    assert!(output.status.success());
-    assert!(String::from_utf8_lossy(&output.stdout).contains("Task not found"));
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Task not found"));
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Task not found"));
+
+    delete_test_db(); // Tear Down
+}
+
+#[test]
+fn test_cli_exit_codes() {
+    setup();
+
+    // 0: success
+    let output = Command::new("cargo")
+        .args(["run", "add", "--name", "Test", "-t"])
+        .output()
+        .expect("Error adding task");
+
+    assert_eq!(output.status.code(), Some(0));
+
+    // 1: usage, the arguments cannot be parsed
+    let output = Command::new("cargo")
+        .args(["run", "update", "--id", "1", "--done", "invalid", "-t"])
+        .output()
+        .expect("Error updating task");
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid value 'invalid'"));
+    assert!(output.stdout.is_empty());
+
+    // 2: not found
+    let output = Command::new("cargo")
+        .args(["run", "done", "9", "-t"])
+        .output()
+        .expect("Error completing task");
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Task not found"));
+
+    // 3: validation
+    let output = Command::new("cargo")
+        .args(["run", "get", "--completed-since", "yesterday", "-t"])
+        .output()
+        .expect("Error reading tasks");
+
+    assert_eq!(output.status.code(), Some(3));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid date 'yesterday'"));
+
+    // 4: storage
+    let output = Command::new("cargo")
+        .args(["run", "sync", "missing-dir/other.db", "-t"])
+        .output()
+        .expect("Error syncing");
+
+    assert_eq!(output.status.code(), Some(4));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Error opening missing-dir/other.db"));
+
+    let output = Command::new("cargo")
+        .args(["run", "get", "-t"])
+        .output()
+        .expect("Error reading tasks");
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("name = Test, is_done = false"), "Failed commands change nothing");
 
     delete_test_db(); // Tear Down
 }
@@ -332,7 +394,7 @@ fn test_cli_add_read_update() {
         .output()
         .expect("Error updating task");
 
-    assert!(String::from_utf8_lossy(&output.stdout).contains("Update"));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Update"));
     assert!(String::from_utf8_lossy(&output.stdout).contains("Test"));
     assert!(String::from_utf8_lossy(&output.stdout).contains("is_done = true"));
 
@@ -378,7 +440,7 @@ fn test_cli_add_read_delete() {
         .output()
         .expect("Error deleting task");
 
-    assert!(String::from_utf8_lossy(&output.stdout).contains(" 1 "));
+    assert!(String::from_utf8_lossy(&output.stderr).contains(" 1 "));
     assert!(String::from_utf8_lossy(&output.stdout).contains("deleted"));
 
     let output = Command::new("cargo")
@@ -392,4 +454,300 @@ fn test_cli_add_read_delete() {
     assert!(!String::from_utf8_lossy(&output.stdout).contains("Test"));
 
     delete_test_db(); // Tear Down
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_cli_backup_restore() {
+    setup();
+    let backup_path = "test_tasks_backup.db";
+
+    Command::new("cargo")
+        .arg("run")
+        .arg("add")
+        .arg("--name")
+        .arg("Before backup")
+        .arg("-t")
+        .output()
+        .expect("Error adding task");
+
+    let output = Command::new("cargo")
+        .arg("run")
+        .arg("backup")
+        .arg(backup_path)
+        .arg("-t")
+        .output()
+        .expect("Error backing up database");
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Backup saved successfully"));
+
+    Command::new("cargo")
+        .arg("run")
+        .arg("add")
+        .arg("--name")
+        .arg("After backup")
+        .arg("-t")
+        .output()
+        .expect("Error adding task");
+
+    let output = Command::new("cargo")
+        .arg("run")
+        .arg("restore-backup")
+        .arg(backup_path)
+        .arg("-t")
+        .output()
+        .expect("Error restoring database");
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Backup restored successfully"));
+
+    let output = Command::new("cargo")
+        .arg("run")
+        .arg("get")
+        .arg("-t")
+        .output()
+        .expect("Error reading tasks");
+
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Before backup"));
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("After backup"));
+
+    let _ = fs::remove_file(backup_path);
+    delete_test_db(); // Tear Down
+}
+
+#[test]
+fn test_cli_shell_runs_piped_commands() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    setup();
+
+    let mut child = Command::new("cargo")
+        .arg("run")
+        .arg("shell")
+        .arg("-t")
+        .env("HOME", std::env::temp_dir())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Error starting shell");
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"add --name \"Shell task\"\nget\nexit\n")
+        .unwrap();
+    let output = child.wait_with_output().expect("Error running shell");
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Add task Shell task"));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("name = Shell task, is_done = false"));
+
+    delete_test_db(); // Tear Down
+}
+
+#[test]
+fn test_cli_remind_fires_once() {
+    setup();
+
+    Command::new("cargo")
+        .arg("run")
+        .arg("add")
+        .arg("--name")
+        .arg("Overdue")
+        .arg("--remind-at")
+        .arg("2000-01-01 09:00")
+        .arg("-t")
+        .output()
+        .expect("Error adding task");
+
+    let output = Command::new("cargo")
+        .arg("run")
+        .arg("remind")
+        .arg("--once")
+        .arg("-t")
+        .output()
+        .expect("Error running reminders");
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Reminder: task 1 \"Overdue\""));
+
+    let output = Command::new("cargo")
+        .arg("run")
+        .arg("remind")
+        .arg("--once")
+        .arg("-t")
+        .output()
+        .expect("Error running reminders");
+
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Reminder:"));
+
+    delete_test_db(); // Tear Down
+}
+
+#[test]
+fn test_cli_done_reopen_and_validation_errors() {
+    setup();
+
+    Command::new("cargo")
+        .args(["run", "add", "--name", "Test 1", "-t"])
+        .output()
+        .expect("Error adding task");
+
+    let output = Command::new("cargo")
+        .args(["run", "done", "1", "-t"])
+        .output()
+        .expect("Error completing task");
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Test 1, is_done = true"));
+
+    let output = Command::new("cargo")
+        .args(["run", "reopen", "1", "-t"])
+        .output()
+        .expect("Error reopening task");
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Test 1, is_done = false"));
+
+    let output = Command::new("cargo")
+        .args(["run", "update", "--id", "1", "--done", "maybe", "-t"])
+        .output()
+        .expect("Error updating task");
+
+    assert!(!output.status.success());
+
+    let output = Command::new("cargo")
+        .args(["run", "done", "9", "-t"])
+        .output()
+        .expect("Error completing task");
+
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Task not found"));
+
+    delete_test_db(); // Tear Down
+}
+
+#[test]
+fn test_cli_quiet_verbose_and_log_file() {
+    setup();
+    let data_dir = std::env::temp_dir().join(format!("rust-to-do-list-{}-e2e-data", std::process::id()));
+    let _ = fs::remove_dir_all(&data_dir);
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "-q", "add", "--name", "Quiet", "-t"])
+        .output()
+        .expect("Error adding task");
+
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("Add task"));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("name = Quiet"));
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "get", "-vv", "--log-file", "-t"])
+        .env("XDG_DATA_HOME", &data_dir)
+        .output()
+        .expect("Error reading tasks");
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("trace: SELECT"));
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("SELECT"), "Logs stay off the standard output");
+
+    let log = fs::read_to_string(data_dir.join("rust-to-do-list").join("rust-to-do-list.log")).unwrap();
+    assert!(log.contains("INFO rust_to_do_list::commands: List tasks"));
+    assert!(log.contains("TRACE sql: SELECT"));
+
+    let _ = fs::remove_dir_all(&data_dir);
+    delete_test_db(); // Tear Down
+}
+
+#[test]
+fn test_cli_profiles_and_dry_run() {
+    setup();
+    let dir = std::env::temp_dir().join(format!("rust-to-do-list-{}-e2e-profiles", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("profiles");
+    fs::write(&config, format!("# Profiles of the e2e test\nwork = {}\n", dir.join("work.db").display())).unwrap();
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--profile", "work", "add", "--name", "Work task"])
+        .env("TODO_CONFIG", &config)
+        .output()
+        .expect("Error adding task");
+
+    assert!(output.status.success());
+    assert!(dir.join("work.db").exists(), "The profile has its own database");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "done", "1", "--dry-run", "--profile", "work"])
+        .env("TODO_CONFIG", &config)
+        .output()
+        .expect("Error completing task");
+
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Work task, is_done = true"));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Dry run, nothing was saved"));
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "get", "-p", "work"])
+        .env("TODO_CONFIG", &config)
+        .output()
+        .expect("Error reading tasks");
+
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Work task, is_done = false"));
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "get", "-t"])
+        .env("TODO_CONFIG", &config)
+        .output()
+        .expect("Error reading tasks");
+
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Work task"), "Other profiles keep their own tasks");
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "get", "--profile", "play"])
+        .env("TODO_CONFIG", &config)
+        .output()
+        .expect("Error reading tasks");
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown profile play"));
+
+    let _ = fs::remove_dir_all(&dir);
+    delete_test_db(); // Tear Down
+}
+
+#[cfg(feature = "json-store")]
+#[test]
+fn test_cli_migrate_storage_from_the_active_json_storage() {
+    let dir = std::env::temp_dir().join(format!("rust-to-do-list-{}-e2e-migrate", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("profiles");
+    fs::write(&config, format!("to_sqlite = {}\nto_json = {}\n", dir.join("first.db").display(), dir.join("second.db").display())).unwrap();
+    let run = |storage: Option<&str>, args: &[&str]| {
+        let mut command = Command::new("cargo");
+        command.args(["run", "--features", "json-store", "--"]).args(args).env("TODO_CONFIG", &config);
+        match storage {
+            Some(storage) => command.env("TODO_STORAGE", storage),
+            None => command.env_remove("TODO_STORAGE"),
+        };
+        command.output().expect("Error running command")
+    };
+
+    assert!(run(Some("json"), &["add", "--name", "Json task", "-p", "to_sqlite"]).status.success());
+    let output = run(Some("json"), &["migrate-storage", "--from", "json", "--to", "sqlite", "-p", "to_sqlite"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("1 task(s) migrated successfully"));
+    assert!(String::from_utf8_lossy(&run(None, &["get", "-p", "to_sqlite"]).stdout).contains("Json task"));
+
+    assert!(run(None, &["add", "--name", "Sqlite task", "-p", "to_json"]).status.success());
+    let output = run(Some("json"), &["migrate-storage", "--from", "sqlite", "--to", "json", "-p", "to_json"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&run(Some("json"), &["get", "-p", "to_json"]).stdout).contains("Sqlite task"));
+
+    let _ = fs::remove_dir_all(&dir);
+}
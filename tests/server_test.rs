@@ -0,0 +1,122 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+fn delete_test_db() {
+    let _ = fs::remove_file("test_tasks.db");
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+fn spawn_server(port: u16) -> Child {
+    // The binary is run directly so killing it does not leave an orphan behind `cargo run`
+    let child = Command::new(env!("CARGO_BIN_EXE_rust-to-do-list"))
+        .arg("serve")
+        .arg("--bind")
+        .arg(format!("127.0.0.1:{port}"))
+        .arg("-t")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .expect("Error spawning server");
+
+    for _ in 0..100 {
+        if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    child
+}
+
+fn add_user(name: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_rust-to-do-list"))
+        .arg("user")
+        .arg("add")
+        .arg(name)
+        .arg("-t")
+        .output()
+        .expect("Error adding user");
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("token = "))
+        .expect("No token printed")
+        .to_string()
+}
+
+fn request(port: u16, token: &str, method: &str, path: &str, body: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).expect("Error connecting to server");
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer {token}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+
+    let status = response
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .expect("Malformed status line");
+    let body = response.split("\r\n\r\n").nth(1).unwrap_or("").to_string();
+
+    (status, body)
+}
+
+#[test]
+fn test_server_tasks_endpoints() {
+    delete_test_db();
+    let token = add_user("tester");
+    let other_token = add_user("other");
+    let port = free_port();
+    let mut server = spawn_server(port);
+
+    let (status, _) = request(port, "wrong", "GET", "/tasks", "");
+    assert_eq!(status, 401);
+
+    let (status, body) = request(port, &token, "GET", "/tasks", "");
+    assert_eq!(status, 200);
+    assert_eq!(body, "[]");
+
+    let (status, body) = request(port, &token, "POST", "/tasks", r#"{"name": "Server task"}"#);
+    assert_eq!(status, 201);
+    assert!(body.contains("Server task"));
+
+    let huge = format!(r#"{{"name": "{}"}}"#, "x".repeat(70 * 1024));
+    let (status, _) = request(port, &token, "POST", "/tasks", &huge);
+    assert_eq!(status, 413);
+
+    let (status, body) = request(port, &token, "PATCH", "/tasks/1", r#"{"is_done": true}"#);
+    assert_eq!(status, 200);
+    assert!(body.contains(r#""is_done":true"#));
+
+    let (status, _) = request(port, &token, "PATCH", "/tasks/99", r#"{"is_done": true}"#);
+    assert_eq!(status, 404);
+
+    let (status, _) = request(port, &other_token, "DELETE", "/tasks/1", "");
+    assert_eq!(status, 404);
+
+    let (status, _) = request(port, &token, "DELETE", "/tasks/1", "");
+    assert_eq!(status, 200);
+
+    let (status, _) = request(port, &token, "DELETE", "/tasks/1", "");
+    assert_eq!(status, 404);
+
+    let (status, body) = request(port, &token, "GET", "/tasks", "");
+    assert_eq!(status, 200);
+    assert_eq!(body, "[]");
+
+    server.kill().unwrap();
+    server.wait().unwrap();
+    delete_test_db(); // Tear Down
+}